@@ -1,9 +1,9 @@
 use derive_more::Display;
-use itertools::iproduct;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::layer::Layout;
+use crate::Gate;
 
 #[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq)]
 pub enum Cost {
@@ -25,6 +25,65 @@ pub enum Gateset {
     CliffordT,
 }
 
+impl Gateset {
+    pub fn contains(&self, gate: &Gate) -> bool {
+        match self {
+            Gateset::Nam => matches!(
+                gate,
+                Gate::H(_) | Gate::X(_) | Gate::RZ { .. } | Gate::CX { .. }
+            ),
+            Gateset::CliffordT => matches!(
+                gate,
+                Gate::H(_)
+                    | Gate::X(_)
+                    | Gate::S(_)
+                    | Gate::Sdg(_)
+                    | Gate::T(_)
+                    | Gate::Tdg(_)
+                    | Gate::CX { .. }
+            ),
+        }
+    }
+    // `gate` in this gate set, up to a global phase. A rotation only rebases to Clifford+T if its
+    // angle is a multiple of pi/4.
+    pub fn rebase(&self, gate: &Gate) -> Result<Vec<Gate>, String> {
+        if self.contains(gate) {
+            return Ok(vec![gate.clone()]);
+        }
+        let rz = |q1: usize, quarters: f64| Gate::RZ {
+            param1: quarters * std::f64::consts::FRAC_PI_4,
+            q1,
+        };
+        match (self, gate) {
+            (_, Gate::B) => Ok(vec![]),
+            (Gateset::Nam, Gate::T(q)) => Ok(vec![rz(*q, 1.0)]),
+            (Gateset::Nam, Gate::Tdg(q)) => Ok(vec![rz(*q, -1.0)]),
+            (Gateset::Nam, Gate::S(q)) => Ok(vec![rz(*q, 2.0)]),
+            (Gateset::Nam, Gate::Sdg(q)) => Ok(vec![rz(*q, -2.0)]),
+            (Gateset::Nam, Gate::Z(q)) => Ok(vec![rz(*q, 4.0)]),
+            (Gateset::CliffordT, Gate::Z(q)) => Ok(vec![Gate::S(*q), Gate::S(*q)]),
+            (Gateset::CliffordT, Gate::RZ { param1, q1 }) => {
+                let quarters = param1 / std::f64::consts::FRAC_PI_4;
+                if (quarters - quarters.round()).abs() > 1e-9 {
+                    return Err(format!("cannot rebase {} to {}", gate, self));
+                }
+                let q = *q1;
+                Ok(match (quarters.round() as i64).rem_euclid(8) {
+                    0 => vec![],
+                    1 => vec![Gate::T(q)],
+                    2 => vec![Gate::S(q)],
+                    3 => vec![Gate::S(q), Gate::T(q)],
+                    4 => vec![Gate::S(q), Gate::S(q)],
+                    5 => vec![Gate::Sdg(q), Gate::Tdg(q)],
+                    6 => vec![Gate::Sdg(q)],
+                    _ => vec![Gate::Tdg(q)],
+                })
+            }
+            _ => Err(format!("cannot rebase {} to {}", gate, self)),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
 #[display("QuartzConfig(cost={cost}, timeout={timeout})")]
 pub struct QuartzConfig {
@@ -32,20 +91,51 @@ pub struct QuartzConfig {
     pub timeout: TimeOut,
     pub ecc_path: String,
     pub gateset: Gateset,
-    pub n_threads: usize,
+    // the number of servers, one per thread of the run if unset
+    #[serde(default)]
+    pub n_threads: Option<usize>,
+    // how many times a failed request may respawn its server before giving up
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
 }
 
-#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq)]
-pub struct VoqcConfig {}
+fn default_max_restarts() -> usize {
+    3
+}
+
+// Limits applied to every invocation of a subprocess oracle.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Default)]
+#[display("SubprocessLimits(timeout={timeout:?}, memory_limit_mb={memory_limit_mb:?})")]
+pub struct SubprocessLimits {
+    // wall-clock seconds, the child is killed on expiry
+    pub timeout: Option<f64>,
+    // address space limit of the child, in MiB
+    pub memory_limit_mb: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
+#[display("VoqcConfig(limits={limits})")]
+pub struct VoqcConfig {
+    #[serde(default)]
+    pub limits: SubprocessLimits,
+}
 
 #[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq)]
 pub struct RoqcConfig {}
 
-#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq)]
-pub struct TketConfig {}
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
+#[display("TketConfig(limits={limits})")]
+pub struct TketConfig {
+    #[serde(default)]
+    pub limits: SubprocessLimits,
+}
 
-#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq)]
-pub struct QiskitConfig {}
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
+#[display("QiskitConfig(limits={limits})")]
+pub struct QiskitConfig {
+    #[serde(default)]
+    pub limits: SubprocessLimits,
+}
 
 #[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
 pub enum OracleName {
@@ -61,16 +151,231 @@ pub enum PreprocessConfig {
     None,
 }
 
+// How SOAM dispatches its windows to the oracle.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq, Default)]
+pub enum Scheduler {
+    // synchronous rounds of non-overlapping windows
+    #[default]
+    Rounds,
+    // windows are dispatched as soon as a worker is free and applied as soon as they return
+    Async,
+    // convex subcircuits of the gate DAG grown `omega` steps around each gate, optimized in
+    // rounds of windows on disjoint qubits; the windows follow qubit locality instead of time
+    Dag,
+}
+
+// Periodic snapshots of the SOAM state, from which an interrupted run can be resumed.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
+#[display("CheckpointConfig(dir={dir:?}, interval={interval})")]
+pub struct CheckpointConfig {
+    // no checkpoints are written when unset
+    pub dir: Option<String>,
+    // minimum number of seconds between two checkpoints
+    #[serde(default = "default_checkpoint_interval")]
+    pub interval: f64,
+}
+
+fn default_checkpoint_interval() -> f64 {
+    600.0
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig {
+            dir: None,
+            interval: default_checkpoint_interval(),
+        }
+    }
+}
+
+// How far the window around a seam extends on each side.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq, Default)]
+pub enum WindowPolicy {
+    // `omega` non-empty layers
+    #[default]
+    Layers,
+    // the given number of gates, however many layers they span
+    #[display("Gates({_0})")]
+    Gates(usize),
+    // `omega` non-empty layers, but no more than the given number of gates
+    #[display("Mixed({_0})")]
+    Mixed(usize),
+}
+
+// Limits after which SOAM stops and returns the current circuit, which is then not guaranteed
+// to be locally optimal. Unset limits do not apply.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Default)]
+#[display(
+    "Budget(time_limit={time_limit:?}, max_rounds={max_rounds:?}, min_improvement={min_improvement:?})"
+)]
+pub struct Budget {
+    // seconds of optimization, including the time spent before a resume
+    pub time_limit: Option<f64>,
+    // rounds of the trace; a round of the async and DAG schedulers is one window per worker
+    pub max_rounds: Option<usize>,
+    // minimum relative cost decrease of a round, e.g. 0.001 for 0.1%
+    pub min_improvement: Option<f64>,
+}
+
+// Splits every window into tiles: the qubits are partitioned into groups of strongly interacting
+// qubits, and the oracle optimizes the gates of each group separately, the tiles of a window in
+// parallel. Gates acting on two groups are left as they are and cut the tiles of their groups in
+// two, so a tiled run ends with every tile locally optimal, not the whole circuit. Does not apply
+// to the DAG scheduler.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq, Default)]
+pub enum Tiling {
+    // the oracle sees the whole window
+    #[default]
+    None,
+    // groups of at most the given number of qubits
+    #[display("Qubits({_0})")]
+    Qubits(usize),
+}
+
+// Seeded randomized exploration. The seams start at a random offset instead of at the multiples
+// of omega, and under the rounds scheduler every round until the seams run out moves the pending
+// seams and shrinks the windows by up to `amount` times omega, and picks the even or odd blocks
+// at random. The gate counts of the `Gates` and `Mixed` window policies shrink by the same
+// fraction. `amount` is between 0 and 1. A run that explored this way is then certified by
+// running again from fresh seams without jitter, so it still ends locally optimal; `restarts`
+// more runs start over from another random offset.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
+#[display("Jitter(seed={seed:?}, amount={amount}, restarts={restarts})")]
+pub struct Jitter {
+    // no randomization when unset
+    pub seed: Option<u64>,
+    #[serde(default = "default_jitter_amount")]
+    pub amount: f64,
+    #[serde(default)]
+    pub restarts: usize,
+}
+
+fn default_jitter_amount() -> f64 {
+    0.25
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Jitter {
+            seed: None,
+            amount: default_jitter_amount(),
+            restarts: 0,
+        }
+    }
+}
+
+// Which oracle results replace their window.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq, Default)]
+pub enum Acceptance {
+    // a lower cost, in at most as many layers as the window
+    #[default]
+    Strict,
+    // lower costs in lexicographic order, e.g. `["Gate", "Depth"]` also accepts an equal number
+    // of gates in a lower depth; the first cost is usually the configured one
+    #[display("Lexicographic({_0:?})")]
+    Lexicographic(Vec<Cost>),
+    // a lower cost in up to the given number of layers more than the window, which are inserted
+    // into the circuit; the DAG scheduler does not count layers
+    #[display("Growth({_0})")]
+    Growth(usize),
+}
+
+// Certifies that a converged run is locally optimal: the oracle runs once more on the window of
+// every layer, and a window whose result the acceptance policy would take is a violation. The
+// windows reached after the time limit of the budget are left unchecked, and the run does not pass.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq, Default)]
+pub enum Certify {
+    #[default]
+    Off,
+    // reports the violations in the results
+    Report,
+    // runs SOAM again from the violations, up to the given number of times, before reporting
+    #[display("Repair({_0})")]
+    Repair(usize),
+}
+
+// How the optimized circuit is written.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Qasm,
+    // the gates as serialized by serde
+    Json,
+}
+
+// Where and how the optimized circuit is written; nothing is written unless `dir` is set. `name`
+// may use `{stem}` (the file name of the circuit without extension), `{index}` (of the config in
+// the sweep) and `{ext}` (of the format).
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq, Eq)]
+#[display("OutputConfig(dir={dir:?}, name={name}, format={format}, gateset={gateset:?})")]
+pub struct OutputConfig {
+    pub dir: Option<String>,
+    #[serde(default = "default_output_name")]
+    pub name: String,
+    #[serde(default)]
+    pub format: OutputFormat,
+    // the gate set to rebase the circuit to, if not the configured one
+    pub gateset: Option<Gateset>,
+}
+
+fn default_output_name() -> String {
+    "{stem}.{index}.opt.{ext}".to_string()
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            dir: None,
+            name: default_output_name(),
+            format: OutputFormat::default(),
+            gateset: None,
+        }
+    }
+}
+
+// Grows omega during a run: SOAM starts with the configured omega, and once a level is locally
+// optimal (or its rounds improve less than `min_improvement`) omega is multiplied by `growth` and
+// the seams are rebuilt, up to `max_omega`. The last level always runs to local optimality.
+#[derive(Deserialize, Debug, Clone, Serialize, Display, PartialEq)]
+#[display(
+    "AdaptiveOmega(max_omega={max_omega:?}, growth={growth}, min_improvement={min_improvement:?})"
+)]
+pub struct AdaptiveOmega {
+    // omega is fixed when unset
+    pub max_omega: Option<usize>,
+    #[serde(default = "default_omega_growth")]
+    pub growth: usize,
+    // minimum relative cost decrease of a round before moving to the next level
+    pub min_improvement: Option<f64>,
+}
+
+fn default_omega_growth() -> usize {
+    2
+}
+
+impl Default for AdaptiveOmega {
+    fn default() -> Self {
+        AdaptiveOmega {
+            max_omega: None,
+            growth: default_omega_growth(),
+            min_improvement: None,
+        }
+    }
+}
+
+// Fields after the `;` are optional in the config files and take their `Default` value.
 macro_rules! config_structs {
-    ($($field:ident: $ftype:ty),*) => {
+    ($($field:ident: $ftype:ty),*; $($ofield:ident: $oftype:ty),*) => {
         #[derive(Deserialize, Debug, Clone, Serialize)]
         pub struct MultipleConfigs {
             $(pub $field: Vec<$ftype>,)*
+            $(#[serde(default = "default_single")] pub $ofield: Vec<$oftype>,)*
         }
 
         #[derive(Deserialize, Debug, Clone, Serialize)]
         pub struct SingleConfig {
             $(pub $field: $ftype,)*
+            $(#[serde(default)] pub $ofield: $oftype,)*
         }
 
         impl MultipleConfigs {
@@ -79,18 +384,29 @@ macro_rules! config_structs {
                 $(
                     unique_elements.insert(AllConfigKeys::$field, self.$field.len() == 1);
                 )*
+                $(
+                    unique_elements.insert(AllConfigKeys::$ofield, self.$ofield.len() == 1);
+                )*
                 unique_elements
             }
 
+            // The cartesian product of all the fields, the first field varying slowest.
             pub fn to_single_configs(&self) -> Vec<SingleConfig> {
-                let combinations = iproduct!(
-                    $(self.$field.iter()),*
-                );
-                let mut single_configs = Vec::new();
-                for combination in combinations {
-                    let ( $( $field, )* ) = combination;
+                let total = 1 $(* self.$field.len())* $(* self.$ofield.len())*;
+                let mut single_configs = Vec::with_capacity(total);
+                for k in 0..total {
+                    let mut stride = total;
+                    $(
+                        stride /= self.$field.len();
+                        let $field = &self.$field[(k / stride) % self.$field.len()];
+                    )*
+                    $(
+                        stride /= self.$ofield.len();
+                        let $ofield = &self.$ofield[(k / stride) % self.$ofield.len()];
+                    )*
                     single_configs.push(SingleConfig {
                         $( $field: $field.clone(), )*
+                        $( $ofield: $ofield.clone(), )*
                     });
                 }
                 single_configs
@@ -102,6 +418,11 @@ macro_rules! config_structs {
                         println!("{}: {:?}", stringify!($field), self.$field[0]);
                     }
                 )*
+                $(
+                    if unique_elements.get(&AllConfigKeys::$ofield).copied().unwrap_or(false) {
+                        println!("{}: {:?}", stringify!($ofield), self.$ofield[0]);
+                    }
+                )*
             }
 
         }
@@ -112,6 +433,11 @@ macro_rules! config_structs {
                         println!("{}: {:?}", stringify!($field), &self.$field);
                     }
                 )*
+                $(
+                    if !unique_elements.get(&AllConfigKeys::$ofield).copied().unwrap_or(true) {
+                        println!("{}: {:?}", stringify!($ofield), &self.$ofield);
+                    }
+                )*
             }
             pub fn non_unique_elements(&self, unique_elements: &HashMap<AllConfigKeys, bool>)->String {
                 let mut result = String::new();
@@ -120,6 +446,11 @@ macro_rules! config_structs {
                         result.push_str(&format!("{}: {:?}, ", stringify!($field), &self.$field));
                     }
                 )*
+                $(
+                    if !unique_elements.get(&AllConfigKeys::$ofield).copied().unwrap_or(true) {
+                        result.push_str(&format!("{}: {:?}, ", stringify!($ofield), &self.$ofield));
+                    }
+                )*
                 result
             }
         }
@@ -127,9 +458,15 @@ macro_rules! config_structs {
         #[derive(PartialEq, Eq, Hash)]
         pub enum AllConfigKeys {
             $( $field, )*
+            $( $ofield, )*
         }
     };
 }
+
+fn default_single<T: Default>() -> Vec<T> {
+    vec![T::default()]
+}
+
 config_structs! {
     circuit_path: String,
     use_soam:bool, //Directly send it to oracle or use soam
@@ -139,7 +476,19 @@ config_structs! {
     cost: Cost,
     gateset: Gateset,
     n_threads: usize,
-    layout: Layout
+    layout: Layout;
+    scheduler: Scheduler,
+    checkpoint: CheckpointConfig,
+    budget: Budget,
+    timeline: bool, //Record a Chrome trace of the oracle calls and round phases
+    window: WindowPolicy,
+    adaptive: AdaptiveOmega,
+    tiling: Tiling,
+    multi_pass: bool, //Alternate ALAP and ASAP re-layered passes until one does not improve
+    jitter: Jitter,
+    acceptance: Acceptance,
+    certify: Certify,
+    output: OutputConfig
 }
 impl MultipleConfigs {
     pub fn read_config(config_path: &String) -> MultipleConfigs {
@@ -154,6 +503,22 @@ impl MultipleConfigs {
 mod tests {
     use super::*;
     #[test]
+    fn test_rebase() {
+        let rz = |quarters: f64| Gate::RZ {
+            param1: quarters * std::f64::consts::FRAC_PI_4,
+            q1: 0,
+        };
+        assert_eq!(Gateset::Nam.rebase(&Gate::T(0)), Ok(vec![rz(1.0)]));
+        assert_eq!(Gateset::Nam.rebase(&Gate::H(0)), Ok(vec![Gate::H(0)]));
+        assert_eq!(
+            Gateset::CliffordT.rebase(&rz(-3.0)),
+            Ok(vec![Gate::Sdg(0), Gate::Tdg(0)])
+        );
+        assert_eq!(Gateset::CliffordT.rebase(&rz(8.0)), Ok(vec![]));
+        assert!(Gateset::CliffordT.rebase(&rz(0.5)).is_err());
+        assert!(Gateset::Nam.rebase(&Gate::Y(0)).is_err());
+    }
+    #[test]
     fn test_save_config() {
         let config = MultipleConfigs {
             circuit_path: vec!["circuit1".to_string()],
@@ -165,14 +530,16 @@ mod tests {
                     timeout: TimeOut::PerGate(0.1),
                     ecc_path: "ecc_path".to_string(),
                     gateset: Gateset::Nam,
-                    n_threads: 1,
+                    n_threads: Some(1),
+                    max_restarts: 3,
                 }),
                 OracleName::Quartz(QuartzConfig {
                     cost: Cost::Depth,
                     timeout: TimeOut::PerGate(0.1),
                     ecc_path: "ecc_path".to_string(),
                     gateset: Gateset::Nam,
-                    n_threads: 1,
+                    n_threads: Some(1),
+                    max_restarts: 3,
                 }),
             ],
             preprocess_config: vec![PreprocessConfig::None],
@@ -180,6 +547,18 @@ mod tests {
             gateset: vec![Gateset::Nam],
             n_threads: vec![1],
             layout: vec![Layout::Dense],
+            scheduler: vec![Scheduler::Rounds],
+            checkpoint: vec![CheckpointConfig::default()],
+            budget: vec![Budget::default()],
+            timeline: vec![false],
+            window: vec![WindowPolicy::Layers],
+            adaptive: vec![AdaptiveOmega::default()],
+            tiling: vec![Tiling::None],
+            multi_pass: vec![false],
+            jitter: vec![Jitter::default()],
+            acceptance: vec![Acceptance::Strict],
+            certify: vec![Certify::Off],
+            output: vec![OutputConfig::default()],
         };
         let config_string = toml::to_string(&config).expect("Failed to serialize config");
         std::fs::write("config.toml", config_string).expect("Failed to write config file");
@@ -79,10 +79,12 @@ impl DAG {
         }
     }
     pub fn get_neighbors(&self, gate_index: GateIndex, steps: usize) -> Vec<GateIndex> {
-        let mut frontier = HashSet::new();
-        let mut visited = HashSet::new();
-        frontier.insert(gate_index);
-        visited.insert(gate_index);
+        self.get_neighborhood(&[gate_index], steps)
+    }
+    // The gates at most `steps` edges away from any of `gate_indices`, in either direction.
+    pub fn get_neighborhood(&self, gate_indices: &[GateIndex], steps: usize) -> Vec<GateIndex> {
+        let mut frontier: HashSet<GateIndex> = gate_indices.iter().copied().collect();
+        let mut visited = frontier.clone();
         for _ in 0..steps {
             let new_neighbors: Vec<_> = frontier
                 .iter()
@@ -110,6 +112,20 @@ impl DAG {
     pub fn set_optimized(&mut self, gate_index: GateIndex) {
         self.unoptimized_gates.remove(&gate_index);
     }
+    pub fn set_unoptimized(&mut self, gate_index: GateIndex) {
+        if gate_index != 0 && gate_index != 1 && self.gate_to_node.contains_key(&gate_index) {
+            self.unoptimized_gates.insert(gate_index);
+        }
+    }
+    pub fn n_unoptimized(&self) -> usize {
+        self.unoptimized_gates.len()
+    }
+    pub fn unoptimized_gates(&self) -> impl Iterator<Item = GateIndex> + '_ {
+        self.unoptimized_gates.iter().copied()
+    }
+    pub fn is_unoptimized(&self, gate_index: GateIndex) -> bool {
+        self.unoptimized_gates.contains(&gate_index)
+    }
 
     pub fn get_gate(&self, gate_index: GateIndex) -> &GateNode {
         &self.graph[self.gate_to_node[&gate_index]]
@@ -1,6 +1,7 @@
 use crate::types::{QubitIndex, Real};
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Gate {
     CCX {
@@ -193,4 +194,34 @@ impl Gate {
             }
         }
     }
+    // The same gate acting on `f(q)` instead of every qubit `q`.
+    pub fn map_qubits(&self, f: impl Fn(QubitIndex) -> QubitIndex) -> Gate {
+        let mut gate = self.clone();
+        match &mut gate {
+            Gate::CCX { q1, q2, q3 } | Gate::CCZ { q1, q2, q3 } => {
+                (*q1, *q2, *q3) = (f(*q1), f(*q2), f(*q3));
+            }
+            Gate::CX { q1, q2 } | Gate::CZ { q1, q2 } | Gate::Swap { q1, q2 } => {
+                (*q1, *q2) = (f(*q1), f(*q2));
+            }
+            Gate::H(q1)
+            | Gate::X(q1)
+            | Gate::Y(q1)
+            | Gate::Z(q1)
+            | Gate::S(q1)
+            | Gate::Sdg(q1)
+            | Gate::SqrtX(q1)
+            | Gate::SqrtXdg(q1)
+            | Gate::T(q1)
+            | Gate::Tdg(q1)
+            | Gate::RX { q1, .. }
+            | Gate::RY { q1, .. }
+            | Gate::RZ { q1, .. }
+            | Gate::U { q1, .. } => {
+                *q1 = f(*q1);
+            }
+            Gate::B => {}
+        }
+        gate
+    }
 }
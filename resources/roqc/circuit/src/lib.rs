@@ -8,4 +8,4 @@ pub use dag::CircuitDag;
 pub use gate::Gate;
 pub use layer::CircuitLayer;
 pub use seq::CircuitSeq;
-pub use seq::{parse_program, write_program};
+pub use seq::{parse_program, try_parse_program, write_program};
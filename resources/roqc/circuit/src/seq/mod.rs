@@ -1,4 +1,4 @@
 pub mod qasm_parser;
 pub mod seq_impl;
-pub use qasm_parser::{parse_program, write_program};
+pub use qasm_parser::{parse_program, try_parse_program, write_program};
 pub use seq_impl::CircuitSeq;
@@ -3,29 +3,39 @@ use crate::Gate;
 use std::fs::File;
 use std::io::prelude::*;
 
-fn extract_qubit_index(qubit_str: &str) -> usize {
-    let start_idx = qubit_str.find('[').expect("Failed to find opening bracket") + 1;
-    let end_idx = qubit_str.find(']').expect("Failed to find closing bracket");
-    qubit_str[start_idx..end_idx]
-        .parse::<usize>()
-        .expect("Failed to parse qubit index")
+fn extract_qubit_index(qubit_str: &str) -> Result<usize, String> {
+    let start_idx = qubit_str
+        .find('[')
+        .ok_or_else(|| format!("Failed to find opening bracket in {}", qubit_str))?
+        + 1;
+    let end_idx = qubit_str
+        .find(']')
+        .ok_or_else(|| format!("Failed to find closing bracket in {}", qubit_str))?;
+    qubit_str
+        .get(start_idx..end_idx)
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or_else(|| format!("Failed to parse qubit index in {}", qubit_str))
 }
 
-fn extract_register_name(qubit_str: &str) -> String {
-    let end_idx = qubit_str.find('[').expect("Failed to find opening bracket");
-    qubit_str[0..end_idx].to_string()
-
+fn extract_register_name(qubit_str: &str) -> Result<String, String> {
+    let end_idx = qubit_str
+        .find('[')
+        .ok_or_else(|| format!("Failed to find opening bracket in {}", qubit_str))?;
+    Ok(qubit_str[0..end_idx].to_string())
 }
 
-fn calculate_qubit_index(qubit_str: &str, qubit_regs: &Vec<(String, usize)>) -> usize {
-    let register_name = extract_register_name(qubit_str);
-    let original_qubit_index = extract_qubit_index(qubit_str);
+fn calculate_qubit_index(
+    qubit_str: &str,
+    qubit_regs: &Vec<(String, usize)>,
+) -> Result<usize, String> {
+    let register_name = extract_register_name(qubit_str)?;
+    let original_qubit_index = extract_qubit_index(qubit_str)?;
     for reg_name in qubit_regs {
         if reg_name.0 == register_name {
-            return original_qubit_index + reg_name.1;
+            return Ok(original_qubit_index + reg_name.1);
         }
     }
-    0
+    Err(format!("Undeclared register in {}", qubit_str))
 }
 
 #[allow(dead_code)]
@@ -39,35 +49,44 @@ fn float_to_int_with_precision(value: f64, precision: f64) -> usize {
     rounded_value
 }
 
-fn extract_and_parse_parameter(param_str: &str) -> f64 {
+fn extract_and_parse_parameter(param_str: &str) -> Result<f64, String> {
     let start_idx = param_str
         .find('(')
-        .expect("Failed to find opening parenthesis")
+        .ok_or_else(|| format!("Failed to find opening parenthesis in {}", param_str))?
         + 1;
     // find the last ')'
     let end_idx = param_str
         .rfind(')')
-        .expect("Failed to find closing parenthesis");
-    let param_str = &param_str[start_idx..end_idx];
+        .ok_or_else(|| format!("Failed to find closing parenthesis in {}", param_str))?;
+    let param_str = param_str
+        .get(start_idx..end_idx)
+        .ok_or_else(|| format!("Malformed parameter {}", param_str))?;
 
     let pi = std::f64::consts::PI;
     let param_str = param_str.replace("PI", &pi.to_string());
     let param_str = param_str.replace('π', &pi.to_string());
 
-    let mut param: f64 =
-        meval::eval_str(param_str).expect("Failed to evaluate parameter expression");
+    let mut param: f64 = meval::eval_str(&param_str)
+        .map_err(|_| format!("Failed to evaluate parameter expression {}", param_str))?;
     if param < 0.0 {
         param = (2.0 * pi) + param;
     }
 
-    param
+    Ok(param)
 }
 
-pub fn parse_program(program: &str) -> CircuitSeq {
+fn token<'a>(tokens: &[&'a str], idx: usize, line: &str) -> Result<&'a str, String> {
+    tokens
+        .get(idx)
+        .copied()
+        .ok_or_else(|| format!("Missing operand in line: {}", line))
+}
+
+pub fn try_parse_program(program: &str) -> Result<CircuitSeq, String> {
     let mut instructions = Vec::new();
     let mut n_qubits = 0;
 
-    let mut qubit_regs : Vec<(String, usize)> = vec![];
+    let mut qubit_regs: Vec<(String, usize)> = vec![];
 
     for line in program.lines() {
         let tokens: Vec<&str> = line
@@ -83,16 +102,16 @@ pub fn parse_program(program: &str) -> CircuitSeq {
             "OPENQASM" => {}
             "INCLUDE" => {}
             "QREG" => {
-                qubit_regs.push((extract_register_name(tokens[1]), n_qubits));
-                n_qubits += extract_qubit_index(tokens[1]);
+                qubit_regs.push((extract_register_name(token(&tokens, 1, line)?)?, n_qubits));
+                n_qubits += extract_qubit_index(token(&tokens, 1, line)?)?;
             }
             "CCX" => {
-                let qubit_str1 = tokens[1];
-                let qubit_str2 = tokens[2];
-                let qubit_str3 = tokens[3];
-                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs);
-                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs);
-                let qubit_idx3 = calculate_qubit_index(qubit_str3, &qubit_regs);
+                let qubit_str1 = token(&tokens, 1, line)?;
+                let qubit_str2 = token(&tokens, 2, line)?;
+                let qubit_str3 = token(&tokens, 3, line)?;
+                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs)?;
+                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs)?;
+                let qubit_idx3 = calculate_qubit_index(qubit_str3, &qubit_regs)?;
                 instructions.push(Gate::CCX {
                     q1: qubit_idx1,
                     q2: qubit_idx2,
@@ -100,12 +119,12 @@ pub fn parse_program(program: &str) -> CircuitSeq {
                 });
             }
             "CCZ" => {
-                let qubit_str1 = tokens[1];
-                let qubit_str2 = tokens[2];
-                let qubit_str3 = tokens[3];
-                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs);
-                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs);
-                let qubit_idx3 = calculate_qubit_index(qubit_str3, &qubit_regs);
+                let qubit_str1 = token(&tokens, 1, line)?;
+                let qubit_str2 = token(&tokens, 2, line)?;
+                let qubit_str3 = token(&tokens, 3, line)?;
+                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs)?;
+                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs)?;
+                let qubit_idx3 = calculate_qubit_index(qubit_str3, &qubit_regs)?;
                 instructions.push(Gate::CCZ {
                     q1: qubit_idx1,
                     q2: qubit_idx2,
@@ -113,121 +132,121 @@ pub fn parse_program(program: &str) -> CircuitSeq {
                 });
             }
             "CX" => {
-                let qubit_str1 = tokens[1];
-                let qubit_str2 = tokens[2];
-                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs);
-                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs);
+                let qubit_str1 = token(&tokens, 1, line)?;
+                let qubit_str2 = token(&tokens, 2, line)?;
+                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs)?;
+                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs)?;
                 instructions.push(Gate::CX {
                     q1: qubit_idx1,
                     q2: qubit_idx2,
                 });
             }
             "CZ" => {
-                let qubit_str1 = tokens[1];
-                let qubit_str2 = tokens[2];
-                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs);
-                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs);
+                let qubit_str1 = token(&tokens, 1, line)?;
+                let qubit_str2 = token(&tokens, 2, line)?;
+                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs)?;
+                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs)?;
                 instructions.push(Gate::CZ {
                     q1: qubit_idx1,
                     q2: qubit_idx2,
                 });
             }
             "H" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::H(qubit_idx));
             }
             "X" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::X(qubit_idx));
             }
             "Y" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::Y(qubit_idx));
             }
             "Z" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::Z(qubit_idx));
             }
             "RX" => {
-                let qubit_str = tokens[1];
-                let param = extract_and_parse_parameter(tokens[0]);
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let param = extract_and_parse_parameter(token(&tokens, 0, line)?)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::RX {
                     param1: param,
                     q1: qubit_idx,
                 });
             }
             "RY" => {
-                let qubit_str = tokens[1];
-                let param = extract_and_parse_parameter(tokens[0]);
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let param = extract_and_parse_parameter(token(&tokens, 0, line)?)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::RY {
                     param1: param,
                     q1: qubit_idx,
                 });
             }
             "RZ" => {
-                let qubit_str = tokens[1];
-                let param = extract_and_parse_parameter(tokens[0]);
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let param = extract_and_parse_parameter(token(&tokens, 0, line)?)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::RZ {
                     param1: param,
                     q1: qubit_idx,
                 });
             }
             "S" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::S(qubit_idx));
             }
             "SDG" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::Sdg(qubit_idx));
             }
             "SQRTX" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::SqrtX(qubit_idx));
             }
             "SQRTXDG" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::SqrtXdg(qubit_idx));
             }
             "SWAP" => {
-                let qubit_str1 = tokens[1];
-                let qubit_str2 = tokens[2];
-                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs);
-                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs);
+                let qubit_str1 = token(&tokens, 1, line)?;
+                let qubit_str2 = token(&tokens, 2, line)?;
+                let qubit_idx1 = calculate_qubit_index(qubit_str1, &qubit_regs)?;
+                let qubit_idx2 = calculate_qubit_index(qubit_str2, &qubit_regs)?;
                 instructions.push(Gate::Swap {
                     q1: qubit_idx1,
                     q2: qubit_idx2,
                 });
             }
             "T" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::T(qubit_idx));
             }
             "TDG" => {
-                let qubit_str = tokens[1];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
+                let qubit_str = token(&tokens, 1, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
                 instructions.push(Gate::Tdg(qubit_idx));
             }
             "U" => {
-                let qubit_str = tokens[1];
-                let theta_str = tokens[2];
-                let phi_str = tokens[3];
-                let lambda_str = tokens[4];
-                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs);
-                let theta = extract_and_parse_parameter(theta_str);
-                let phi = extract_and_parse_parameter(phi_str);
-                let lambda = extract_and_parse_parameter(lambda_str);
+                let qubit_str = token(&tokens, 1, line)?;
+                let theta_str = token(&tokens, 2, line)?;
+                let phi_str = token(&tokens, 3, line)?;
+                let lambda_str = token(&tokens, 4, line)?;
+                let qubit_idx = calculate_qubit_index(qubit_str, &qubit_regs)?;
+                let theta = extract_and_parse_parameter(theta_str)?;
+                let phi = extract_and_parse_parameter(phi_str)?;
+                let lambda = extract_and_parse_parameter(lambda_str)?;
                 instructions.push(Gate::U {
                     q1: qubit_idx,
                     theta,
@@ -239,11 +258,15 @@ pub fn parse_program(program: &str) -> CircuitSeq {
             "ID" => {}
             "MEASURE" => {}
             "//" => {}
-            _ => panic!("Unknown gate: {}", tokens[0]),
+            _ => return Err(format!("Unknown gate: {}", tokens[0])),
         }
     }
 
-    CircuitSeq::new(instructions, n_qubits)
+    Ok(CircuitSeq::new(instructions, n_qubits))
+}
+
+pub fn parse_program(program: &str) -> CircuitSeq {
+    try_parse_program(program).unwrap_or_else(|err| panic!("{}", err))
 }
 
 pub fn write_program(
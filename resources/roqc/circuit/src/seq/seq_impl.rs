@@ -1,4 +1,4 @@
-use super::qasm_parser::parse_program;
+use super::qasm_parser::{parse_program, try_parse_program};
 use crate::config::Cost;
 use crate::Gate;
 use rayon::prelude::*;
@@ -155,6 +155,9 @@ impl CircuitSeq {
     pub fn new_from_source(source: &str) -> Self {
         parse_program(source)
     }
+    pub fn try_new_from_source(source: &str) -> Result<Self, String> {
+        try_parse_program(source)
+    }
     pub fn new_from_file(path: &Path) -> Self {
         //print more details
         let expanded_path = shellexpand::env(&path.to_string_lossy())
@@ -13,15 +13,22 @@ struct CsvRecord {
     time: f32,
     oracle_time: f32,
     n_seams_total: usize,
+    n_oracle_errors: usize,
+    n_oracle_timeouts: usize,
+    n_oracle_restarts: usize,
+    converged: bool,
+    n_seams_pending: usize,
 }
-pub fn analyze(config_path: &str) {
-    let result_path = config_path.replace("configs", "results");
-    let results_string = std::fs::read_to_string(&result_path).expect("failed to read result file");
-    let configs_string = std::fs::read_to_string(config_path).expect("failed to read config file");
-    let results: MultipleResults =
-        toml::from_str(&results_string).expect("failed to parse config file");
-    let config: MultipleConfigs =
-        toml::from_str(&configs_string).expect("failed to parse config file");
+// Prints the results of a sweep, grouped by the configs they differ in, and writes them as CSV
+// next to the results. `config_path` is the config of the sweep.
+pub fn analyze(result_path: &str, config_path: &str) -> Result<(), String> {
+    let read = |path: &str| {
+        std::fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))
+    };
+    let results: MultipleResults = toml::from_str(&read(result_path)?)
+        .map_err(|err| format!("failed to parse {}: {}", result_path, err))?;
+    let config: MultipleConfigs = toml::from_str(&read(config_path)?)
+        .map_err(|err| format!("failed to parse {}: {}", config_path, err))?;
     let unique_elements = config.unique_config_elements();
     //print unique elements first
     println!("********************************* Common Configs *********************************");
@@ -44,11 +51,18 @@ pub fn analyze(config_path: &str) {
             time: config_result.result.time,
             oracle_time: config_result.result.oracle_time,
             n_seams_total: config_result.result.n_seams_total,
+            n_oracle_errors: config_result.result.n_oracle_errors,
+            n_oracle_timeouts: config_result.result.n_oracle_timeouts,
+            n_oracle_restarts: config_result.result.n_oracle_restarts,
+            converged: config_result.result.converged,
+            n_seams_pending: config_result.result.n_seams_pending,
         });
     }
-    let mut wtr = csv::Writer::from_path(result_path.clone().replace("toml", "csv"))
-        .expect("failed to create csv file");
+    let csv_path = result_path.replace("toml", "csv");
+    let mut wtr = csv::Writer::from_path(&csv_path)
+        .map_err(|err| format!("failed to create {}: {}", csv_path, err))?;
     for record in records_csv {
-        wtr.serialize(record).expect("failed to serialize record");
+        wtr.serialize(record).map_err(|err| err.to_string())?;
     }
+    wtr.flush().map_err(|err| err.to_string())
 }
@@ -0,0 +1,169 @@
+use crate::error::SoamError;
+use crate::optimizer::trace::RoundTrace;
+use crate::optimizer::SoamRunner;
+use crate::oracles::oracle_utils::OracleEnum;
+use crate::results::SingleResult;
+use crate::utils::timeline::Timeline;
+use circuit::config::{
+    Budget, Cost, Gateset, OracleName, PreprocessConfig, Scheduler, SingleConfig,
+};
+use circuit::layer::Layout;
+use circuit::{CircuitLayer, CircuitSeq};
+use std::sync::Arc;
+
+// The options of `optimize`. The `circuit_path` of the config only names the checkpoints of the
+// run, and its `output` is ignored: writing the circuit is up to the caller.
+#[derive(Debug, Clone)]
+pub struct OptimizeOptions {
+    pub config: SingleConfig,
+    // continue from the latest checkpoint of the config, if there is one
+    pub resume: bool,
+}
+
+impl OptimizeOptions {
+    // SOAM with `oracle_name` on windows of 200 layers, minimizing the gate count over the Nam
+    // gate set with the rounds scheduler, on one thread per CPU.
+    pub fn new(oracle_name: OracleName) -> Self {
+        OptimizeOptions {
+            config: SingleConfig {
+                circuit_path: String::new(),
+                use_soam: true,
+                omega: 200,
+                oracle_name,
+                preprocess_config: PreprocessConfig::None,
+                cost: Cost::Gate,
+                gateset: Gateset::Nam,
+                n_threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+                layout: Layout::One,
+                scheduler: Scheduler::Rounds,
+                checkpoint: Default::default(),
+                budget: Budget::default(),
+                timeline: false,
+                window: Default::default(),
+                adaptive: Default::default(),
+                tiling: Default::default(),
+                multi_pass: false,
+                jitter: Default::default(),
+                acceptance: Default::default(),
+                certify: Default::default(),
+                output: Default::default(),
+            },
+            resume: false,
+        }
+    }
+}
+
+impl From<SingleConfig> for OptimizeOptions {
+    fn from(config: SingleConfig) -> Self {
+        OptimizeOptions {
+            config,
+            resume: false,
+        }
+    }
+}
+
+// The optimized circuit with the statistics of its run.
+#[derive(Debug)]
+pub struct OptimizeOutcome {
+    pub circuit: CircuitSeq,
+    pub result: SingleResult,
+    pub trace: Vec<RoundTrace>,
+    // set if the config enables the timeline
+    pub timeline: Option<Arc<Timeline>>,
+}
+
+// Optimizes `circuit` as configured by `opts`. Failed oracle calls leave their windows unchanged
+// and are counted in the result; only starting the run can fail.
+pub fn optimize(circuit: CircuitSeq, opts: OptimizeOptions) -> Result<OptimizeOutcome, SoamError> {
+    optimize_with(circuit, opts, None)
+}
+
+// `optimize` with `oracle_runner` in place of the oracle of the config, if it is set.
+pub(crate) fn optimize_with(
+    circuit: CircuitSeq,
+    opts: OptimizeOptions,
+    oracle_runner: Option<OracleEnum>,
+) -> Result<OptimizeOutcome, SoamError> {
+    let config = opts.config;
+    let original = CircuitLayer::from_seq(circuit.clone(), config.layout.clone());
+    let mut builder = SoamRunner::builder(config).resume(opts.resume);
+    if let Some(oracle_runner) = oracle_runner {
+        builder = builder.oracle_runner(oracle_runner);
+    }
+    let mut runner = builder.build(circuit)?;
+    let now = std::time::Instant::now();
+    runner.soam();
+    let time = now.elapsed().as_secs_f32() + runner.time_resumed;
+    runner.oracle_runner.shutdown();
+    let result = SingleResult {
+        original_depth: original.cost(&Cost::Depth),
+        optimized_depth: runner.circ.cost(&Cost::Depth),
+        original_gates: original.cost(&Cost::Gate),
+        optimized_gates: runner.circ.cost(&Cost::Gate),
+        n_rounds: runner.n_rounds,
+        time,
+        oracle_time: runner.time_oracle,
+        n_seams_total: runner.n_seams_total,
+        n_oracle_errors: runner.oracle_stats.errors(),
+        n_oracle_timeouts: runner.oracle_stats.timeouts(),
+        n_oracle_restarts: runner.oracle_runner.n_restarts(),
+        converged: runner.converged,
+        n_seams_pending: runner.n_seams_pending,
+        omega_levels: runner.omega_levels,
+        passes: runner.passes,
+        certificate: runner.certificate,
+    };
+    Ok(OptimizeOutcome {
+        circuit: runner.circ.to_seq(),
+        result,
+        trace: runner.trace,
+        timeline: runner.timeline,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use circuit::config::VoqcConfig;
+    use circuit::Gate;
+
+    #[test]
+    fn test_optimize() {
+        let circ = CircuitSeq::new(vec![Gate::H(0), Gate::H(0), Gate::CX { q1: 0, q2: 1 }], 2);
+        let mut opts = OptimizeOptions::new(OracleName::Voqc(VoqcConfig {
+            limits: Default::default(),
+        }));
+        opts.config.omega = 2;
+        opts.config.n_threads = 1;
+        let identity = || Some(OracleEnum::Identity);
+        let outcome = optimize_with(circ.clone(), opts.clone(), identity()).unwrap();
+        assert_eq!(outcome.result.original_gates, 3);
+        assert_eq!(outcome.result.optimized_gates, outcome.circuit.gates.len());
+        assert_eq!(outcome.result.optimized_gates, 3);
+        assert!(outcome.result.converged);
+
+        opts.config.omega = 0;
+        assert!(matches!(
+            optimize_with(circ.clone(), opts.clone(), identity()),
+            Err(SoamError::Options(_))
+        ));
+        opts.config.omega = 2;
+        opts.config.jitter.amount = 1.5;
+        assert!(matches!(
+            optimize_with(circ.clone(), opts.clone(), identity()),
+            Err(SoamError::Options(_))
+        ));
+        opts.config.jitter.amount = 0.25;
+        opts.config.checkpoint.dir = Some("$SOAM_UNSET_CHECKPOINT_DIR".to_string());
+        assert!(matches!(
+            optimize_with(circ.clone(), opts.clone(), identity()),
+            Err(SoamError::Checkpoint(_))
+        ));
+        opts.config.checkpoint.dir = None;
+        let outside = CircuitSeq::new(vec![Gate::H(2)], 2);
+        assert!(matches!(
+            optimize_with(outside, opts, identity()),
+            Err(SoamError::Options(_))
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+use crate::oracles::oracle_utils::OracleError;
+use derive_more::{Display, From};
+use rayon::ThreadPoolBuildError;
+
+// The errors of the library API. Failures of single oracle calls are not errors: the window is
+// kept as it is and the failure is counted in the result.
+#[derive(Debug, Display, From)]
+pub enum SoamError {
+    #[display("invalid options: {_0}")]
+    #[from(ignore)]
+    Options(String),
+    #[display("failed to start the oracle: {_0}")]
+    Oracle(OracleError),
+    #[display("failed to build the thread pool: {_0}")]
+    Pool(ThreadPoolBuildError),
+    #[display("checkpoint failed: {_0}")]
+    #[from(ignore)]
+    Checkpoint(String),
+}
+
+impl std::error::Error for SoamError {}
@@ -1,5 +1,11 @@
 pub mod analyze;
+pub mod api;
+pub mod error;
 pub mod optimizer;
 pub mod oracles;
 pub mod results;
+pub mod scaling;
+pub mod sweep;
 pub mod utils;
+pub use api::{optimize, OptimizeOptions, OptimizeOutcome};
+pub use error::SoamError;
@@ -1,79 +1,434 @@
-use circuit::config::Cost;
+use circuit::config::{
+    Budget, Cost, Gateset, MultipleConfigs, OracleName, OutputConfig, OutputFormat, QiskitConfig,
+    QuartzConfig, RoqcConfig, Scheduler, SingleConfig, SubprocessLimits, TimeOut, TketConfig,
+    VoqcConfig,
+};
 use circuit::layer::Layout;
-use circuit::{config::MultipleConfigs, config::SingleConfig, CircuitLayer, CircuitSeq};
-use rayon::ThreadPoolBuilder;
-use shellexpand;
-use soam::optimizer::SoamRunner;
+use circuit::{CircuitLayer, Gate};
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::de::DeserializeOwned;
+use soam::optimizer::trace::RoundTrace;
 use soam::results::{ConfigResult, MultipleResults, SingleResult};
-use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-const CORRECTNESS_CHECK: bool = false;
-const DUMP: bool = false;
-
-fn main() {
-    // env_logger::init();
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <file_path>", args[0]);
-        std::process::exit(1);
+use soam::sweep::SweepBudget;
+use soam::OptimizeOptions;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Parallel quantum circuit optimization with SOAM.
+///
+/// Exit status: 0 on success, 1 if `verify` finds the circuits different, 2 on errors.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Optimize one circuit, configured by flags only
+    Optimize(OptimizeArgs),
+    /// Run every config of a TOML sweep config, writing the results under `results`
+    Sweep(SweepArgs),
+    /// Run the circuit of a config over its thread counts, and write speedup and efficiency
+    /// tables as CSV next to the results
+    Scale(ScaleArgs),
+    /// Summarize the results of a sweep, as text and as CSV next to them
+    Analyze {
+        /// The results file of the sweep
+        results: String,
+        /// The config of the sweep [default: the results path under `configs`]
+        #[arg(long)]
+        config: Option<String>,
+    },
+    /// Print statistics of circuits
+    Stats {
+        /// Circuits in OpenQASM 2.0 or JSON
+        #[arg(required = true)]
+        circuits: Vec<PathBuf>,
+    },
+    /// Convert a circuit between formats and gate sets
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Qasm or Json [default: from the extension of the output]
+        #[arg(long, value_parser = variant::<OutputFormat>)]
+        format: Option<OutputFormat>,
+        /// Nam or CliffordT, rebases the gates
+        #[arg(long, value_parser = variant::<Gateset>)]
+        gateset: Option<Gateset>,
+    },
+    /// Check that two circuits are equivalent up to a global phase, by simulating them on random
+    /// states
+    Verify {
+        first: PathBuf,
+        second: PathBuf,
+        /// Number of random states, at least one
+        #[arg(long, default_value_t = 4, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        trials: usize,
+        /// Circuits on more qubits are refused, the simulation takes 2^n amplitudes
+        #[arg(long, default_value_t = 20)]
+        max_qubits: usize,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+#[value(rename_all = "PascalCase")]
+enum Oracle {
+    Voqc,
+    Roqc,
+    Quartz,
+    Qiskit,
+    Tket,
+}
+
+#[derive(Args)]
+struct OptimizeArgs {
+    /// The circuit, in OpenQASM 2.0
+    circuit: PathBuf,
+    #[arg(long, value_enum, default_value_t = Oracle::Voqc)]
+    oracle: Oracle,
+    /// The ECC set of Quartz
+    #[arg(long, required_if_eq("oracle", "Quartz"))]
+    ecc_path: Option<String>,
+    /// Seconds per oracle call
+    #[arg(long)]
+    oracle_timeout: Option<f64>,
+    #[arg(long, default_value_t = 200)]
+    omega: usize,
+    /// Gate, Depth or Mixed
+    #[arg(long, default_value = "Gate", value_parser = variant::<Cost>)]
+    cost: Cost,
+    /// Nam or CliffordT
+    #[arg(long, default_value = "Nam", value_parser = variant::<Gateset>)]
+    gateset: Gateset,
+    /// One or Dense
+    #[arg(long, default_value = "One", value_parser = variant::<Layout>)]
+    layout: Layout,
+    /// Rounds, Async or Dag
+    #[arg(long, default_value = "Rounds", value_parser = variant::<Scheduler>)]
+    scheduler: Scheduler,
+    /// [default: the number of CPUs]
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Seconds after which SOAM stops with the current circuit
+    #[arg(long)]
+    time_limit: Option<f64>,
+    #[arg(long)]
+    max_rounds: Option<usize>,
+    /// Where to write the optimized circuit
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Qasm or Json [default: from the extension of the output]
+    #[arg(long, requires = "output", value_parser = variant::<OutputFormat>)]
+    format: Option<OutputFormat>,
+    /// Nam or CliffordT, rebases the optimized circuit when it is written
+    #[arg(long, requires = "output", value_parser = variant::<Gateset>)]
+    rebase: Option<Gateset>,
+}
+
+#[derive(Args)]
+struct SweepArgs {
+    /// The sweep config
+    config: String,
+    /// Continue every config from its latest checkpoint
+    #[arg(long)]
+    resume: bool,
+    /// Write the optimized circuits to this directory, overriding `output.dir`
+    #[arg(long)]
+    output_dir: Option<String>,
+    /// Analyze the results once the sweep is done
+    #[arg(long)]
+    analyze: bool,
+    /// Run configs concurrently while their n_threads sum to at most this [default: one config
+    /// at a time]
+    #[arg(long)]
+    max_threads: Option<usize>,
+    /// Run configs concurrently while their oracle servers sum to at most this [default:
+    /// --max-threads]
+    #[arg(long, requires = "max_threads")]
+    max_oracle_servers: Option<usize>,
+}
+
+#[derive(Args)]
+struct ScaleArgs {
+    /// A config listing a single circuit and config, with several n_threads
+    config: String,
+    /// Thread counts overriding the n_threads of the config, e.g. 1,2,4,8
+    #[arg(long, value_delimiter = ',')]
+    threads: Vec<usize>,
+    /// Runs per thread count
+    #[arg(short, long, default_value_t = 3)]
+    repetitions: usize,
+}
+
+// Parses a config enum from its name in the config files, e.g. `Gate` for `Cost::Gate`.
+fn variant<T: DeserializeOwned>(name: &str) -> Result<T, String> {
+    toml::Value::String(name.to_string())
+        .try_into()
+        .map_err(|_| format!("unknown value `{}`", name))
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("soam=info")).init();
+    let cli = Cli::parse();
+    let status = match cli.command {
+        Command::Optimize(args) => optimize(args),
+        Command::Sweep(args) => sweep(args),
+        Command::Scale(args) => scale(args),
+        Command::Analyze { results, config } => {
+            let config = config.unwrap_or_else(|| results.replace("results", "configs"));
+            soam::analyze::analyze(&results, &config).map(|()| ExitCode::SUCCESS)
+        }
+        Command::Stats { circuits } => stats(&circuits),
+        Command::Convert {
+            input,
+            output,
+            format,
+            gateset,
+        } => convert(&input, &output, format, gateset),
+        Command::Verify {
+            first,
+            second,
+            trials,
+            max_qubits,
+            seed,
+        } => verify(&first, &second, trials, max_qubits, seed),
+    };
+    status.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        ExitCode::from(2)
+    })
+}
+
+fn optimize(args: OptimizeArgs) -> Result<ExitCode, String> {
+    let limits = SubprocessLimits {
+        timeout: args.oracle_timeout,
+        memory_limit_mb: None,
+    };
+    let oracle_name = match args.oracle {
+        Oracle::Voqc => OracleName::Voqc(VoqcConfig { limits }),
+        Oracle::Roqc => OracleName::Roqc(RoqcConfig {}),
+        Oracle::Qiskit => OracleName::Qiskit(QiskitConfig { limits }),
+        Oracle::Tket => OracleName::Tket(TketConfig { limits }),
+        Oracle::Quartz => OracleName::Quartz(QuartzConfig {
+            cost: args.cost.clone(),
+            timeout: args
+                .oracle_timeout
+                .map_or(TimeOut::PerGate(0.1), TimeOut::PerSegment),
+            ecc_path: args.ecc_path.unwrap_or_default(),
+            gateset: args.gateset.clone(),
+            n_threads: None,
+            max_restarts: 3,
+        }),
+    };
+    let output = match &args.output {
+        Some(path) => {
+            let name = path
+                .file_name()
+                .ok_or_else(|| format!("{} is not a file name", path.display()))?;
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            OutputConfig {
+                dir: Some(dir.unwrap_or(Path::new(".")).display().to_string()),
+                name: name.to_string_lossy().into_owned(),
+                format: args
+                    .format
+                    .unwrap_or_else(|| soam::results::format_of(path)),
+                gateset: args.rebase,
+            }
+        }
+        None => OutputConfig::default(),
+    };
+    let mut opts = OptimizeOptions::new(oracle_name);
+    let config = &mut opts.config;
+    config.circuit_path = args.circuit.display().to_string();
+    config.omega = args.omega;
+    config.cost = args.cost;
+    config.gateset = args.gateset;
+    config.layout = args.layout;
+    config.scheduler = args.scheduler;
+    if let Some(threads) = args.threads {
+        config.n_threads = threads;
+    }
+    config.budget = Budget {
+        time_limit: args.time_limit,
+        max_rounds: args.max_rounds,
+        min_improvement: None,
+    };
+    config.output = output;
+    soam::utils::cleanup::install_interrupt_handler();
+    let (result, output_path, _) = single_run(&opts.config, false, 0, "")?;
+    println!(
+        "gates {} -> {}, depth {} -> {} in {:.2}s",
+        result.original_gates,
+        result.optimized_gates,
+        result.original_depth,
+        result.optimized_depth,
+        result.time
+    );
+    match (&opts.config.output.dir, output_path) {
+        (Some(_), Some(path)) => println!("Wrote {}", path),
+        (Some(_), None) => return Err("failed to write the optimized circuit".to_string()),
+        (None, _) => {}
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn sweep(args: SweepArgs) -> Result<ExitCode, String> {
+    soam::utils::cleanup::install_interrupt_handler();
+    let budget = match args.max_threads {
+        Some(threads) => SweepBudget {
+            threads,
+            oracle_servers: args.max_oracle_servers.unwrap_or(threads),
+        },
+        None => SweepBudget::sequential(),
+    };
+    run(&args.config, args.resume, args.output_dir, budget)?;
+    if args.analyze {
+        soam::analyze::analyze(&soam::results::result_path(&args.config), &args.config)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn scale(args: ScaleArgs) -> Result<ExitCode, String> {
+    let mut config = MultipleConfigs::read_config(&args.config);
+    if !args.threads.is_empty() {
+        config.n_threads = args.threads;
+    }
+    let configs = soam::scaling::scaling_configs(&config, args.repetitions)?;
+    soam::utils::cleanup::install_interrupt_handler();
+    // one run at a time, as concurrent runs would slow each other down
+    let results = run_configs(&args.config, configs, false, SweepBudget::sequential())?;
+    let rows = soam::scaling::scaling_table(&results.results)?;
+    println!(
+        "{:>9} {:>12} {:>10} {:>10} {:>12} {:>10} {:>10}",
+        "threads", "time", "speedup", "eff.", "oracle time", "speedup", "eff."
+    );
+    for row in rows.iter() {
+        println!(
+            "{:>9} {:>6.2}±{:<5.2} {:>10.2} {:>10.2} {:>6.2}±{:<5.2} {:>10.2} {:>10.2}",
+            row.n_threads,
+            row.time_median,
+            row.time_stddev,
+            row.time_speedup,
+            row.time_efficiency,
+            row.oracle_time_median,
+            row.oracle_time_stddev,
+            row.oracle_time_speedup,
+            row.oracle_time_efficiency
+        );
+    }
+    println!(
+        "Wrote {}",
+        soam::scaling::write_scaling(&args.config, &rows)?
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+fn stats(circuits: &[PathBuf]) -> Result<ExitCode, String> {
+    for path in circuits {
+        let circ = soam::results::read_circuit(path)?;
+        let mut kinds: BTreeMap<String, usize> = BTreeMap::new();
+        for gate in circ.gates.iter().filter(|gate| !matches!(gate, Gate::B)) {
+            let name = gate.to_string();
+            let kind = name
+                .split(['(', ' '])
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            *kinds.entry(kind).or_default() += 1;
+        }
+        let layers = CircuitLayer::from_seq(circ.clone(), Layout::Dense);
+        println!("{}", path.display());
+        println!("  qubits: {}", circ.num_qubits);
+        println!("  gates: {}", layers.gate_count());
+        println!("  depth: {}", layers.depth());
+        println!(
+            "  two-qubit gates: {}",
+            circ.gates
+                .iter()
+                .filter(|gate| gate.qubits().len() == 2)
+                .count()
+        );
+        for (kind, count) in kinds {
+            println!("  {}: {}", kind, count);
+        }
     }
-    let config_path = &args[1];
-    run(config_path);
-    soam::analyze::analyze(config_path);
-}
-fn single_run(config: &SingleConfig) -> SingleResult {
-    let layout = config.layout.clone();
-    let circuit_file = PathBuf::from(config.circuit_path.clone());
-    let circuit = CircuitLayer::from_seq(
-        CircuitSeq::new_from_file(circuit_file.as_path()),
-        layout.clone(),
+    Ok(ExitCode::SUCCESS)
+}
+
+fn convert(
+    input: &Path,
+    output: &Path,
+    format: Option<OutputFormat>,
+    gateset: Option<Gateset>,
+) -> Result<ExitCode, String> {
+    let circ = soam::results::read_circuit(input)?;
+    let config = OutputConfig {
+        format: format.unwrap_or_else(|| soam::results::format_of(output)),
+        gateset,
+        ..Default::default()
+    };
+    soam::results::write_circuit(&config, output, &circ)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(
+    first: &Path,
+    second: &Path,
+    trials: usize,
+    max_qubits: usize,
+    seed: u64,
+) -> Result<ExitCode, String> {
+    let (a, b) = (
+        soam::results::read_circuit(first)?,
+        soam::results::read_circuit(second)?,
     );
-    // let circuit = CircuitLayer::from_seq(
-    //     CircuitLayer::from_seq(
-    //         CircuitSeq::new_from_file(circuit_file.as_path()),
-    //         Layout::Dense,
-    //     )
-    //     .right_layout()
-    //     .to_seq(),
-    //     layout.clone(),
-    // );
-
-    let original_depth = circuit.cost(&Cost::Depth);
-    let original_gates = circuit.cost(&Cost::Gate);
-    let config = config.clone();
-    let _ = ThreadPoolBuilder::new()
-        .num_threads(config.n_threads)
-        .build_global();
-    let mut soam_runner = SoamRunner::new(config.to_owned(), 12345, circuit, layout.clone());
-    let now = std::time::Instant::now();
-    soam_runner.soam();
-    let optimization_time = now.elapsed().as_secs_f32();
-    soam_runner.oracle_runner.shutdown();
-    let new_circuit = soam_runner.circ.clone();
-    if DUMP {
-        let str = new_circuit.to_seq().dump();
-        let expanded_path = shellexpand::env(&config.circuit_path)
-            .expect("failed to expand path")
-            .into_owned();
-        let file_name = expanded_path + ".eval";
-        let mut file = File::create(file_name).unwrap();
-        file.write_all(str.as_bytes()).unwrap();
+    if a.num_qubits.max(b.num_qubits) > max_qubits {
+        return Err(format!(
+            "{} qubits are too many to simulate, see --max-qubits",
+            a.num_qubits.max(b.num_qubits)
+        ));
     }
-    if CORRECTNESS_CHECK {
-        soam_runner.correctness_check(&new_circuit);
+    if soam::utils::statevector::equivalent(&a, &b, trials, seed) {
+        println!("equivalent");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("not equivalent");
+        Ok(ExitCode::FAILURE)
     }
-    SingleResult {
-        original_depth,
-        optimized_depth: new_circuit.cost(&Cost::Depth),
-        original_gates,
-        optimized_gates: new_circuit.cost(&Cost::Gate),
-        n_rounds: soam_runner.n_rounds,
-        time: optimization_time,
-        oracle_time: soam_runner.time_oracle,
-        n_seams_total: soam_runner.n_seams_total,
+}
+
+fn single_run(
+    config: &SingleConfig,
+    resume: bool,
+    index: usize,
+    timeline_path: &str,
+) -> Result<(SingleResult, Option<String>, Vec<RoundTrace>), String> {
+    let circuit = soam::results::read_circuit(Path::new(&config.circuit_path))?;
+    let opts = OptimizeOptions {
+        config: config.clone(),
+        resume,
+    };
+    let outcome = soam::optimize(circuit, opts).map_err(|err| err.to_string())?;
+    if let Some(timeline) = &outcome.timeline {
+        timeline.write(timeline_path);
     }
+    let output_path = soam::results::output_path(&config.output, &config.circuit_path, index)?
+        .and_then(|path| {
+            match soam::results::write_circuit(&config.output, &path, &outcome.circuit) {
+                Ok(()) => Some(path.display().to_string()),
+                Err(err) => {
+                    eprintln!("Failed to write the optimized circuit: {}", err);
+                    None
+                }
+            }
+        });
+    Ok((outcome.result, output_path, outcome.trace))
 }
 
 // fn single_test_range(config: &SingleConfig) -> SingleResult {
@@ -125,17 +480,57 @@ fn single_run(config: &SingleConfig) -> SingleResult {
 //     }
 // }
 
-fn run(config_path: &String) {
-    let config = MultipleConfigs::read_config(config_path);
-    let single_configs = config.to_single_configs();
-
-    let results: Vec<ConfigResult> = single_configs
-        .iter()
-        .map(|single_config| ConfigResult {
-            config: single_config.clone(),
-            result: single_run(single_config),
-        })
-        .collect();
-    let results = MultipleResults { results };
-    soam::results::write_results(config_path, &results);
+fn run(
+    config_path: &str,
+    resume: bool,
+    output_dir: Option<String>,
+    budget: SweepBudget,
+) -> Result<(), String> {
+    let config = MultipleConfigs::read_config(&config_path.to_string());
+    let mut single_configs = config.to_single_configs();
+    if let Some(dir) = output_dir {
+        for config in single_configs.iter_mut() {
+            config.output.dir = Some(dir.clone());
+        }
+    }
+    run_configs(config_path, single_configs, resume, budget).map(|_| ())
+}
+
+// Runs the configs of `config_path` as `budget` allows, writing the results next to it.
+fn run_configs(
+    config_path: &str,
+    single_configs: Vec<SingleConfig>,
+    resume: bool,
+    budget: SweepBudget,
+) -> Result<MultipleResults, String> {
+    soam::results::check_output_paths(&single_configs)?;
+    // Results are written after every config, so an interrupted sweep keeps the finished ones.
+    // They are kept in the order of the configs, whatever order the configs finish in.
+    let mut finished: Vec<Option<(ConfigResult, Vec<RoundTrace>)>> =
+        (0..single_configs.len()).map(|_| None).collect();
+    soam::sweep::run_concurrently(
+        &single_configs,
+        budget,
+        |index, single_config| {
+            let timeline_path = soam::results::timeline_path(config_path, index);
+            single_run(single_config, resume, index, &timeline_path)
+        },
+        |index, (result, output_path, trace)| {
+            let config_result = ConfigResult {
+                config: single_configs[index].clone(),
+                result,
+                output_path,
+            };
+            finished[index] = Some((config_result, trace));
+            let (results, traces): (Vec<ConfigResult>, Vec<Vec<RoundTrace>>) =
+                finished.iter().flatten().cloned().unzip();
+            let written = soam::results::write_results(config_path, &MultipleResults { results })
+                .and_then(|()| soam::results::write_trace(config_path, &traces));
+            if let Err(err) = written {
+                eprintln!("Failed to write the results: {}", err);
+            }
+        },
+    )?;
+    let (results, _): (Vec<ConfigResult>, Vec<_>) = finished.into_iter().flatten().unzip();
+    Ok(MultipleResults { results })
 }
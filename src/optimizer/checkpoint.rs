@@ -0,0 +1,183 @@
+use super::optimizer_impl::JitterState;
+use super::trace::{OmegaLevel, PassTrace, RoundTrace};
+use crate::utils::ftree::FenwickTree;
+use circuit::config::{Budget, Certify, CheckpointConfig, OutputConfig, SingleConfig};
+use circuit::CircuitLayer;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+// A snapshot of a SOAM run: the circuit, the seams still to be optimized and the counters.
+// Restoring it and optimizing the remaining seams gives the same guarantee as an uninterrupted
+// run, since every window changed since the last check has a seam at both ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    // the config of the run, so that a checkpoint is only resumed with the same config
+    pub config: String,
+    pub circ: CircuitLayer,
+    pub ftree: FenwickTree,
+    pub seams: Vec<usize>,
+    pub n_round: usize,
+    pub n_rounds: usize,
+    pub n_seams_total: usize,
+    pub time_oracle: f32,
+    // optimization time spent before this checkpoint
+    pub time: f32,
+    pub trace: Vec<RoundTrace>,
+    // the current omega of an adaptive run, and its levels so far
+    pub omega: usize,
+    pub omega_levels: Vec<OmegaLevel>,
+    pub passes: Vec<PassTrace>,
+    pub jitter: Option<JitterState>,
+}
+
+// The config as it is compared between runs. The checkpoint settings and the budget may change,
+// e.g. to continue a run stopped by its budget with a larger one.
+pub fn config_key(config: &SingleConfig) -> Result<String, String> {
+    let mut config = config.clone();
+    config.checkpoint = CheckpointConfig::default();
+    config.budget = Budget::default();
+    config.certify = Certify::default();
+    config.output = OutputConfig::default();
+    toml::to_string(&config).map_err(|err| format!("failed to serialize the config: {}", err))
+}
+
+// Checkpoints are named after the circuit and a hash of the config, so that the configs of a
+// sweep sharing a directory do not overwrite each other.
+pub fn checkpoint_path(config: &SingleConfig) -> Result<Option<PathBuf>, String> {
+    let Some(dir) = config.checkpoint.dir.as_ref() else {
+        return Ok(None);
+    };
+    let dir = shellexpand::env(dir)
+        .map_err(|err| format!("failed to expand {}: {}", dir, err))?
+        .into_owned();
+    let stem = Path::new(&config.circuit_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // FNV-1a, which unlike `DefaultHasher` is stable across builds
+    let hash = config_key(config)?
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    Ok(Some(
+        Path::new(&dir).join(format!("{}-{:016x}.ckpt", stem, hash)),
+    ))
+}
+
+impl Checkpoint {
+    // Writes to a temporary file first, so that a crash while writing keeps the previous one.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let temp_path = path.with_extension("ckpt.tmp");
+        let file = File::create(&temp_path).map_err(|err| err.to_string())?;
+        bincode::serialize_into(BufWriter::new(file), self).map_err(|err| err.to_string())?;
+        std::fs::rename(&temp_path, path).map_err(|err| err.to_string())
+    }
+
+    // Loads the checkpoint of `config`, if there is one. A checkpoint written with another
+    // config is an error rather than being silently ignored.
+    pub fn load(config: &SingleConfig) -> Result<Option<Checkpoint>, String> {
+        let Some(path) = checkpoint_path(config)? else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&path).map_err(|err| format!("{:?}: {}", path, err))?;
+        let checkpoint: Checkpoint = bincode::deserialize_from(BufReader::new(file))
+            .map_err(|err| format!("{:?}: {}", path, err))?;
+        if checkpoint.config != config_key(config)? {
+            return Err(format!("{:?} was written with a different config", path));
+        }
+        Ok(Some(checkpoint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use circuit::config::{Cost, Gateset, OracleName, PreprocessConfig, Scheduler, VoqcConfig};
+    use circuit::layer::Layout;
+    use circuit::CircuitSeq;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let dir = std::env::temp_dir().join("soam_checkpoint_test");
+        let mut config = SingleConfig {
+            circuit_path: "benchmarks/test.qasm".to_string(),
+            use_soam: true,
+            omega: 4,
+            oracle_name: OracleName::Voqc(VoqcConfig {
+                limits: Default::default(),
+            }),
+            preprocess_config: PreprocessConfig::None,
+            cost: Cost::Gate,
+            gateset: Gateset::Nam,
+            n_threads: 1,
+            layout: Layout::Dense,
+            scheduler: Scheduler::Rounds,
+            checkpoint: CheckpointConfig {
+                dir: Some(dir.to_string_lossy().into_owned()),
+                interval: 1.0,
+            },
+            budget: Budget::default(),
+            timeline: false,
+            window: Default::default(),
+            adaptive: Default::default(),
+            tiling: Default::default(),
+            multi_pass: false,
+            jitter: Default::default(),
+            acceptance: Default::default(),
+            certify: Default::default(),
+            output: Default::default(),
+        };
+        assert!(Checkpoint::load(&config).unwrap().is_none());
+
+        let circ = CircuitLayer::from_seq(
+            CircuitSeq::new_from_source(
+                "OPENQASM 2.0;\nqreg q[2];\nh q[0];\nrz(0.5) q[1];\ncx q[0], q[1];\n",
+            ),
+            Layout::Dense,
+        );
+        let checkpoint = Checkpoint {
+            config: config_key(&config).unwrap(),
+            ftree: FenwickTree::from_iter(vec![1; circ.len()]),
+            circ,
+            seams: vec![0, 2],
+            n_round: 3,
+            n_rounds: 3,
+            n_seams_total: 7,
+            time_oracle: 1.5,
+            time: 2.0,
+            trace: vec![],
+            omega: 4,
+            omega_levels: vec![],
+            passes: vec![],
+            jitter: None,
+        };
+        let path = checkpoint_path(&config).unwrap().unwrap();
+        checkpoint.save(&path).unwrap();
+
+        // the checkpoint interval does not identify the run
+        config.checkpoint.interval = 60.0;
+        let loaded = Checkpoint::load(&config).unwrap().unwrap();
+        assert_eq!(loaded.circ.to_seq().dump(), checkpoint.circ.to_seq().dump());
+        assert_eq!(loaded.ftree, checkpoint.ftree);
+        assert_eq!(loaded.seams, checkpoint.seams);
+        assert_eq!(loaded.n_seams_total, 7);
+
+        // but the rest of the config does, and the checkpoint is then refused
+        config.omega = 8;
+        let mut other = checkpoint.clone();
+        other.config = config_key(&config).unwrap();
+        config.omega = 4;
+        other.save(&path).unwrap();
+        assert!(Checkpoint::load(&config).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,6 @@
+pub mod checkpoint;
 pub mod optimizer_impl;
+pub mod tiles;
+pub mod trace;
 pub mod utils;
 pub use optimizer_impl::SoamRunner;
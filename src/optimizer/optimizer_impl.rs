@@ -1,113 +1,754 @@
-use crate::oracles::oracle_utils::OracleEnum;
-use crate::oracles::qiskit::qiskit_impl::Qiskit;
-use crate::oracles::quartz::quartz_impl::Quartz;
-use crate::oracles::roqc_oracle::Roqc;
-use crate::oracles::tket::tket_impl::Tket;
-use crate::oracles::voqc::Voqc;
+use super::checkpoint::{checkpoint_path, config_key, Checkpoint};
+use super::tiles::Tiles;
+use super::trace::{Certificate, OmegaLevel, PassTrace, Relayout, RoundTrace, Violation};
+use crate::error::SoamError;
+use crate::oracles::oracle_utils::{validate_output, OracleEnum, OracleStats};
 use crate::utils::ftree::FenwickTree;
-use circuit::config::{OracleName, SingleConfig};
+use crate::utils::rng::Rng;
+use crate::utils::timeline::{Timeline, PID_ROUNDS, PID_WORKERS};
+use circuit::config::{
+    Acceptance, Certify, Cost, OracleName, Scheduler, SingleConfig, Tiling, WindowPolicy,
+};
+use circuit::layer::circuit_layer::Layer;
 use circuit::layer::Layout;
-use circuit::{CircuitLayer, Gate};
+use circuit::{CircuitDag, CircuitLayer, CircuitSeq, Gate};
 
 use itertools::Itertools;
-// use log::{debug, info};
+use log::{info, warn};
 use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+// The updates of an improved window: non-empty layer index, gate count index and layers.
+type WindowUpdates = (
+    Vec<(usize, isize)>,
+    Vec<(usize, isize)>,
+    Vec<(usize, Vec<Gate>)>,
+);
+
+// An improved window: the updates that replace it in place, or the segment to splice in its place
+// if the segment has more layers than the window.
+enum Rewrite {
+    InPlace(WindowUpdates),
+    Splice(CircuitLayer),
+}
+
+// State shared by the workers of the asynchronous scheduler. `in_flight` maps the first layer of
+// every window being optimized to its end, seam and worker; windows in it are disjoint, and only
+// the worker that owns a window writes its layers. A grown window moves the windows after it, so
+// a worker looks its window up again when it is done.
+struct AsyncState {
+    circ: CircuitLayer,
+    ftree: FenwickTree,
+    gtree: FenwickTree,
+    pending: BTreeSet<usize>,
+    in_flight: BTreeMap<usize, (usize, usize, usize)>,
+    // set when a budget ends the run; no window is dispatched afterwards
+    stopped: bool,
+    // windows applied so far; every `round_size` of them make up a round of the trace
+    n_applied: usize,
+    round: RoundTrace,
+    latencies: Vec<f32>,
+    trace: Vec<RoundTrace>,
+}
+
+impl AsyncState {
+    fn overlaps_in_flight(&self, left: usize, right: usize) -> bool {
+        self.in_flight
+            .range(..right)
+            .next_back()
+            .is_some_and(|(_, &(end, _, _))| end > left)
+    }
+    // The window in flight of a worker.
+    fn window_of_worker(&self, worker: usize) -> (usize, usize) {
+        self.in_flight
+            .iter()
+            .find(|(_, &(_, _, owner))| owner == worker)
+            .map(|(&left, &(right, _, _))| (left, right))
+            .expect("the worker has a window in flight")
+    }
+    // Moves the seams and the windows in flight from layer `from` on right by `by` layers, after
+    // a window ending at `from` grew by as many.
+    fn shift(&mut self, from: usize, by: usize) {
+        let shift = |layer: usize| if layer >= from { layer + by } else { layer };
+        self.pending = self.pending.iter().map(|&seam| shift(seam)).collect();
+        // windows are disjoint, so a window moves whole or not at all
+        self.in_flight = self
+            .in_flight
+            .iter()
+            .map(|(&left, &(right, seam, worker))| {
+                let by = if left >= from { by } else { 0 };
+                (left + by, (right + by, seam + by, worker))
+            })
+            .collect();
+    }
+    // The seams that are still to be optimized, including those of the windows in flight.
+    fn seams(&self) -> Vec<usize> {
+        let in_flight = self.in_flight.values().map(|&(_, seam, _)| seam);
+        let seams: BTreeSet<usize> = self.pending.iter().copied().chain(in_flight).collect();
+        seams.into_iter().collect()
+    }
+    // The first pending seam whose window is free, together with its window.
+    fn next_window(&self, policy: &WindowPolicy, omega: usize) -> Option<(usize, (usize, usize))> {
+        self.pending.iter().find_map(|&seam| {
+            let (left, right) = window_of(
+                &self.ftree,
+                &self.gtree,
+                policy,
+                seam,
+                omega,
+                self.circ.len(),
+            );
+            (!self.overlaps_in_flight(left, right)).then_some((seam, (left, right)))
+        })
+    }
+}
+
+// `policy` with its gate count shrunk as a window of `radius` layers is from one of `omega`.
+fn shrunk(policy: &WindowPolicy, radius: usize, omega: usize) -> WindowPolicy {
+    let shrink = |gates: usize| (gates * radius / omega).max(1);
+    match policy {
+        WindowPolicy::Layers => WindowPolicy::Layers,
+        WindowPolicy::Gates(gates) => WindowPolicy::Gates(shrink(*gates)),
+        WindowPolicy::Mixed(gates) => WindowPolicy::Mixed(shrink(*gates)),
+    }
+}
+
+// The window around `seam` under `policy`, as a range of layers. `ftree` indexes the non-empty
+// layers and `gtree` the gates of each layer.
+fn window_of(
+    ftree: &FenwickTree,
+    gtree: &FenwickTree,
+    policy: &WindowPolicy,
+    seam: usize,
+    omega: usize,
+    n_layers: usize,
+) -> (usize, usize) {
+    let around = |tree: &FenwickTree, radius: usize, bound: usize| {
+        let id = tree.prefix_sum(seam, 0);
+        (
+            tree.index_of(id.saturating_sub(radius)),
+            tree.index_of((id + radius).min(bound)),
+        )
+    };
+    let n_gates = || gtree.prefix_sum(gtree.len(), 0);
+    match policy {
+        WindowPolicy::Layers => around(ftree, omega, n_layers),
+        WindowPolicy::Gates(gates) => around(gtree, *gates, n_gates()),
+        WindowPolicy::Mixed(gates) => {
+            let (layer_left, layer_right) = around(ftree, omega, n_layers);
+            let (gate_left, gate_right) = around(gtree, *gates, n_gates());
+            (layer_left.max(gate_left), layer_right.min(gate_right))
+        }
+    }
+}
+
+// Whether `round` decreased the cost by less than the fraction `min` of its cost.
+fn improved_less(round: &RoundTrace, min: f64) -> bool {
+    let decrease = round.cost_before.saturating_sub(round.cost_after);
+    round.cost_before > 0 && (decrease as f64) < min * round.cost_before as f64
+}
+
+// Up to `n_windows` windows of unoptimized gates of `dag`, as seeds and the convex hulls of the
+// gates at most `omega` edges away from them, acting on disjoint qubits.
+fn dag_windows(dag: &CircuitDag, omega: usize, n_windows: usize) -> Vec<(usize, Vec<usize>)> {
+    let mut windows = vec![];
+    let mut busy = BTreeSet::new();
+    for seed in dag.graph.unoptimized_gates() {
+        if windows.len() == n_windows || busy.len() == dag.num_qubits {
+            break;
+        }
+        if dag
+            .graph
+            .get_gate(seed)
+            .gate
+            .qubits()
+            .iter()
+            .any(|q| busy.contains(q))
+        {
+            continue;
+        }
+        let indices = dag.make_convex(dag.graph.get_neighbors(seed, omega));
+        let qubits: BTreeSet<usize> = indices
+            .iter()
+            .flat_map(|idx| dag.graph.get_gate(*idx).gate.qubits())
+            .collect();
+        if qubits.is_disjoint(&busy) {
+            busy.extend(qubits);
+            windows.push((seed, indices));
+        }
+    }
+    windows
+}
+
+// The positions in `dag.to_seq()` of the gates of `dag` not yet optimized.
+fn unoptimized_positions(dag: &CircuitDag) -> Vec<usize> {
+    dag.graph
+        .toposort()
+        .into_iter()
+        .filter(|idx| *idx != dag.start_node && *idx != dag.final_node)
+        .enumerate()
+        .filter(|(_, idx)| dag.graph.is_unoptimized(*idx))
+        .map(|(position, _)| position)
+        .collect()
+}
+
+// `circ` with every gate moved as early (`asap`) or as late as possible. Under `Layout::One` the
+// gates keep one layer each, in the order of the re-layered circuit.
+fn relayout(circ: &CircuitLayer, asap: bool, layout: &Layout) -> CircuitLayer {
+    let relayered = if asap {
+        circ.left_layout()
+    } else {
+        circ.right_layout()
+    };
+    match layout {
+        Layout::Dense => relayered,
+        Layout::One => CircuitLayer::from_seq(relayered.to_seq(), Layout::One),
+    }
+}
+
+// The number of gates of every layer, for the gate count index.
+fn gate_counts(circ: &CircuitLayer) -> FenwickTree {
+    FenwickTree::from_iter(circ.layers.iter().map(|layer| layer.gates.len()))
+}
+
+// Splices both indices like `CircuitLayer::splice_many` splices the layers.
+fn splice_counts(
+    ftree: &mut FenwickTree,
+    gtree: &mut FenwickTree,
+    splices: &[(usize, usize, CircuitLayer)],
+) {
+    let counts = |count: fn(&Layer) -> usize| {
+        splices
+            .iter()
+            .map(|(left, right, segment)| {
+                (*left..*right, segment.layers.iter().map(count).collect())
+            })
+            .collect()
+    };
+    ftree.splice_many(counts(|layer| !layer.is_empty() as usize));
+    gtree.splice_many(counts(|layer| layer.gates.len()));
+}
+// The state of a jittered run, see `Jitter`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JitterState {
+    rng: Rng,
+    // whether the current run is the jitter-free run certifying the previous one
+    certifying: bool,
+    restarts_left: usize,
+}
+
 pub struct SoamRunner {
     config: SingleConfig,
     omega: usize,
     pub oracle_runner: OracleEnum,
     ftree: FenwickTree,
+    gtree: FenwickTree,
     pub n_round: usize,
     pub time_oracle: f32,
     pub circ: CircuitLayer,
     pub layout: Layout,
     pub n_rounds: usize,
     pub n_seams_total: usize,
+    pub oracle_stats: OracleStats,
+    // optimization time spent before the run was resumed from a checkpoint
+    pub time_resumed: f32,
+    resumed_seams: Option<Vec<usize>>,
+    started: Instant,
+    // where checkpoints are saved and the config key they are saved with, if they are enabled
+    checkpoint_to: Option<(PathBuf, String)>,
+    last_checkpoint: Mutex<Instant>,
+    // cost decrease of all the improved windows so far, negative if a lexicographic acceptance
+    // traded the configured cost for another one
+    cost_decrease: AtomicIsize,
+    pub trace: Vec<RoundTrace>,
+    pub timeline: Option<Arc<Timeline>>,
+    // false if a budget ended the run while seams were pending
+    pub converged: bool,
+    pub n_seams_pending: usize,
+    pub omega_levels: Vec<OmegaLevel>,
+    pub passes: Vec<PassTrace>,
+    // set if `certify` is on and the run converged
+    pub certificate: Option<Certificate>,
+    // the qubit groups the windows are cut along, if tiled
+    tiles: Option<Tiles>,
+    jitter: Option<JitterState>,
+    // the `n_threads` threads of this run; a sweep runs every config on its own pool
+    pool: Arc<ThreadPool>,
 }
 
-impl SoamRunner {
-    pub fn new(config: SingleConfig, port: u16, circ: CircuitLayer, layout: Layout) -> Self {
-        println!("First round: {:?}", config);
-        let oracle_runner = match config.oracle_name {
-            OracleName::Quartz(ref quartz_config) => {
-                let oracle_runner = Quartz::new(quartz_config.clone(), port);
-                OracleEnum::SyncQuartz(oracle_runner)
-            }
-            OracleName::Voqc(ref voqc_config) => {
-                let oracle_runner = Voqc::new(voqc_config.clone());
-                OracleEnum::Voqc(oracle_runner)
-            }
-            OracleName::Roqc(ref roqc_config) => {
-                let oracle_runner = Roqc::new(roqc_config.clone());
-                OracleEnum::Roqc(oracle_runner)
-            }
-            OracleName::Qiskit(ref qiskit_config) => {
-                let oracle_runner = Qiskit::new(qiskit_config.clone());
-                OracleEnum::Qiskit(oracle_runner)
-            }
-            OracleName::Tket(ref tket_config) => {
-                let oracle_runner = Tket::new(tket_config.clone());
-                OracleEnum::Tket(oracle_runner)
-            }
+// Builds a `SoamRunner` from a config and a circuit; see `SoamRunner::builder`.
+pub struct SoamRunnerBuilder {
+    config: SingleConfig,
+    resume: bool,
+    // replaces the oracle of the config
+    oracle_runner: Option<OracleEnum>,
+}
+
+impl SoamRunnerBuilder {
+    // Whether to continue from the latest checkpoint of the config, if there is one.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+    // Runs `oracle_runner` instead of starting the oracle of the config; the tests use it to
+    // stub the oracle out.
+    pub(crate) fn oracle_runner(mut self, oracle_runner: OracleEnum) -> Self {
+        self.oracle_runner = Some(oracle_runner);
+        self
+    }
+    // Checks the config against the circuit, and starts the oracle.
+    pub fn build(self, circ: CircuitSeq) -> Result<SoamRunner, SoamError> {
+        check_config(&self.config, &circ)?;
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.config.n_threads)
+            .build()?;
+        let oracle_runner = match self.oracle_runner {
+            Some(oracle_runner) => oracle_runner,
+            None => OracleEnum::new(&self.config.oracle_name, self.config.n_threads)?,
         };
+        let checkpoint_to = match checkpoint_path(&self.config).map_err(SoamError::Checkpoint)? {
+            Some(path) => Some((
+                path,
+                config_key(&self.config).map_err(SoamError::Checkpoint)?,
+            )),
+            None => None,
+        };
+        let circ = CircuitLayer::from_seq(circ, self.config.layout.clone());
+        let mut runner = SoamRunner::new(self.config, circ, oracle_runner, pool, checkpoint_to);
+        if self.resume {
+            if runner.resume().map_err(SoamError::Checkpoint)? {
+                info!("Resuming from checkpoint");
+            } else {
+                info!("No checkpoint found, starting from scratch");
+            }
+        }
+        Ok(runner)
+    }
+}
+
+// The options a run would otherwise panic or loop on.
+fn check_config(config: &SingleConfig, circ: &CircuitSeq) -> Result<(), SoamError> {
+    if config.omega == 0 {
+        return Err(SoamError::Options("omega must be positive".to_string()));
+    }
+    if config.n_threads == 0 {
+        return Err(SoamError::Options("n_threads must be positive".to_string()));
+    }
+    if let OracleName::Quartz(quartz_config) = &config.oracle_name {
+        if quartz_config.ecc_path.is_empty() {
+            return Err(SoamError::Options("Quartz needs an ECC path".to_string()));
+        }
+    }
+    if !(0.0..=1.0).contains(&config.jitter.amount) {
+        return Err(SoamError::Options(
+            "the jitter amount must be between 0 and 1".to_string(),
+        ));
+    }
+    if let Some(dir) = &config.checkpoint.dir {
+        shellexpand::env(dir).map_err(|err| SoamError::Checkpoint(err.to_string()))?;
+    }
+    match circ
+        .gates
+        .iter()
+        .find(|gate| gate.qubits().iter().any(|&q| q >= circ.num_qubits))
+    {
+        Some(gate) => Err(SoamError::Options(format!(
+            "{} acts outside the {} qubits of the circuit",
+            gate, circ.num_qubits
+        ))),
+        None => Ok(()),
+    }
+}
+
+impl SoamRunner {
+    // A builder starting from `config`; its `layout` lays out the circuit.
+    pub fn builder(config: SingleConfig) -> SoamRunnerBuilder {
+        SoamRunnerBuilder {
+            config,
+            resume: false,
+            oracle_runner: None,
+        }
+    }
+    fn new(
+        config: SingleConfig,
+        circ: CircuitLayer,
+        mut oracle_runner: OracleEnum,
+        pool: ThreadPool,
+        checkpoint_to: Option<(PathBuf, String)>,
+    ) -> Self {
+        let timeline = config.timeline.then(|| Arc::new(Timeline::new()));
+        if let Some(timeline) = &timeline {
+            oracle_runner.set_timeline(timeline.clone());
+        }
 
         SoamRunner {
             config: config.clone(),
             omega: config.omega,
             oracle_runner,
             ftree: FenwickTree::from_iter(vec![1; circ.len()]),
+            gtree: gate_counts(&circ),
             n_round: 0,
             time_oracle: 0.0,
             circ: circ.clone(),
-            layout,
+            layout: config.layout.clone(),
             n_rounds: 0,
             n_seams_total: 0,
+            oracle_stats: OracleStats::default(),
+            time_resumed: 0.0,
+            resumed_seams: None,
+            started: Instant::now(),
+            checkpoint_to,
+            last_checkpoint: Mutex::new(Instant::now()),
+            cost_decrease: AtomicIsize::new(0),
+            trace: Vec::new(),
+            timeline,
+            converged: true,
+            n_seams_pending: 0,
+            omega_levels: Vec::new(),
+            passes: Vec::new(),
+            certificate: None,
+            tiles: match config.tiling {
+                Tiling::None => None,
+                Tiling::Qubits(max_qubits) => Some(Tiles::new(&circ.to_seq(), max_qubits)),
+            },
+            jitter: config.jitter.seed.map(|seed| JitterState {
+                rng: Rng::new(seed),
+                certifying: false,
+                restarts_left: config.jitter.restarts,
+            }),
+            pool: Arc::new(pool),
         }
     }
-    fn id_of_non_empty_layer(&self, id: usize) -> usize {
-        self.ftree.prefix_sum(id, 0)
+    // Restores the latest checkpoint of this config, if there is one, so that `soam` continues
+    // from it. Returns whether a checkpoint was found.
+    pub fn resume(&mut self) -> Result<bool, String> {
+        let Some(checkpoint) = Checkpoint::load(&self.config)? else {
+            return Ok(false);
+        };
+        self.circ = checkpoint.circ;
+        self.ftree = checkpoint.ftree;
+        self.gtree = gate_counts(&self.circ);
+        self.n_round = checkpoint.n_round;
+        self.n_rounds = checkpoint.n_rounds;
+        self.n_seams_total = checkpoint.n_seams_total;
+        self.time_oracle = checkpoint.time_oracle;
+        self.time_resumed = checkpoint.time;
+        self.resumed_seams = Some(checkpoint.seams);
+        self.trace = checkpoint.trace;
+        self.omega = checkpoint.omega;
+        self.omega_levels = checkpoint.omega_levels;
+        self.passes = checkpoint.passes;
+        self.jitter = checkpoint.jitter;
+        Ok(true)
     }
-
-    fn reverse_id_of_non_empty_layer(&self, id: usize) -> usize {
-        self.ftree.index_of(id)
+    // The checkpoint path if checkpoints are enabled and one is due (or `force` is set).
+    fn checkpoint_due(&self, force: bool) -> Option<PathBuf> {
+        let (path, _) = self.checkpoint_to.as_ref()?;
+        let mut last_checkpoint = self.last_checkpoint.lock().unwrap();
+        if !force && last_checkpoint.elapsed().as_secs_f64() < self.config.checkpoint.interval {
+            return None;
+        }
+        *last_checkpoint = Instant::now();
+        Some(path.clone())
+    }
+    fn checkpoint(&self, seams: Vec<usize>) -> Checkpoint {
+        let config = self.checkpoint_to.as_ref().map(|(_, key)| key.clone());
+        Checkpoint {
+            config: config.unwrap_or_default(),
+            circ: self.circ.clone(),
+            ftree: self.ftree.clone(),
+            seams,
+            n_round: self.n_round,
+            n_rounds: self.n_rounds,
+            n_seams_total: self.n_seams_total,
+            time_oracle: self.time_oracle,
+            time: self.elapsed(),
+            trace: self.trace.clone(),
+            omega: self.omega,
+            omega_levels: self.omega_levels.clone(),
+            passes: self.passes.clone(),
+            jitter: self.jitter.clone(),
+        }
+    }
+    // Whether a budget of the config ends the run after `rounds` rounds of the trace, whatever
+    // the scheduler. `round` is the round that just finished, if one did.
+    fn out_of_budget(&self, rounds: usize, round: Option<&RoundTrace>) -> bool {
+        let budget = &self.config.budget;
+        self.out_of_time()
+            || budget.max_rounds.is_some_and(|max| rounds >= max)
+            || budget
+                .min_improvement
+                .is_some_and(|min| round.is_some_and(|round| improved_less(round, min)))
+    }
+    fn out_of_time(&self) -> bool {
+        let elapsed = self.elapsed() as f64;
+        self.config
+            .budget
+            .time_limit
+            .is_some_and(|limit| elapsed >= limit)
+    }
+    // The omega of the next level of an adaptive run, if any. Windows bounded by gates alone do
+    // not depend on omega, so they have a single level.
+    fn next_omega(&self) -> Option<usize> {
+        let adaptive = &self.config.adaptive;
+        let max_omega = adaptive.max_omega?;
+        if matches!(self.config.window, WindowPolicy::Gates(_)) || self.omega >= max_omega {
+            return None;
+        }
+        Some((self.omega * adaptive.growth).clamp(self.omega + 1, max_omega))
     }
-    fn find_seams(&self, seams: &Vec<usize>) -> (Vec<usize>, Vec<usize>) {
+    // Whether the improvements of the current level dried up after `round`, so that the run
+    // moves on to the next level.
+    fn level_dry(&self, round: &RoundTrace) -> bool {
+        self.config
+            .adaptive
+            .min_improvement
+            .is_some_and(|min| self.next_omega().is_some() && improved_less(round, min))
+    }
+    fn rng(&mut self) -> &mut Rng {
+        &mut self.jitter.as_mut().expect("not a jittered run").rng
+    }
+    // Whether the current run is jittered, rather than unjittered or certifying. Only the rounds
+    // scheduler jitters its rounds.
+    fn exploring(&self) -> bool {
+        self.config.scheduler == Scheduler::Rounds
+            && self.config.jitter.amount > 0.0
+            && self.jitter.as_ref().is_some_and(|j| !j.certifying)
+    }
+    // The largest move of a seam, or shrink of a window, in layers.
+    fn jitter_range(&self) -> usize {
+        (self.config.jitter.amount * self.omega as f64) as usize
+    }
+    // Moves every seam by up to `jitter_range` layers either way.
+    fn jitter_seams(&mut self, seams: Vec<usize>) -> Vec<usize> {
+        let (range, n_layers) = (self.jitter_range(), self.circ.len());
+        let mut seams: Vec<usize> = seams
+            .into_iter()
+            .map(|seam| {
+                (seam + self.rng().below(2 * range + 1))
+                    .saturating_sub(range)
+                    .min(n_layers.saturating_sub(1))
+            })
+            .collect();
+        seams.sort();
+        seams.dedup();
+        seams
+    }
+    // Whether to run again once the seams ran out: to certify a jittered run, or to restart from
+    // another offset.
+    fn next_jitter_run(&mut self) -> bool {
+        let exploring = self.exploring();
+        let Some(jitter) = &mut self.jitter else {
+            return false;
+        };
+        if exploring {
+            info!("Certifying the jittered run");
+            jitter.certifying = true;
+            true
+        } else if jitter.restarts_left > 0 {
+            info!("Restarting from another offset");
+            jitter.restarts_left -= 1;
+            jitter.certifying = false;
+            true
+        } else {
+            false
+        }
+    }
+    fn elapsed(&self) -> f32 {
+        self.time_resumed + self.started.elapsed().as_secs_f32()
+    }
+    fn save_checkpoint(path: PathBuf, checkpoint: Checkpoint) {
+        match checkpoint.save(&path) {
+            Ok(()) => info!("Checkpoint written to {:?}", path),
+            Err(err) => warn!("Failed to write checkpoint {:?}: {}", path, err),
+        }
+    }
+    // Runs the oracle on a segment. If the oracle fails or its output cannot replace the
+    // segment, the failure is counted and the original segment is returned unchanged.
+    fn run_oracle(&self, segment: CircuitSeq, task_id: usize) -> CircuitSeq {
+        match self
+            .oracle_runner
+            .run_single(segment.clone(), task_id)
+            .and_then(|optimized| validate_output(&segment, optimized, &self.config.gateset))
+        {
+            Ok(optimized) => optimized,
+            Err(err) => {
+                self.oracle_stats.record(&err);
+                segment
+            }
+        }
+    }
+    fn span(&self, name: &str, pid: u32, tid: usize, start: Instant, args: serde_json::Value) {
+        if let Some(timeline) = &self.timeline {
+            timeline.span(name, "soam", pid, tid, start, args);
+        }
+    }
+    // Runs the oracle on every tile of a segment, the groups in parallel and the tiles of a group
+    // in turn. The `task_id`s of the groups are distinct across the windows optimized at the same
+    // time.
+    fn run_tiles(&self, tiles: &Tiles, segment: CircuitSeq, task_id: usize) -> CircuitSeq {
+        let mut split = tiles.split(&segment);
+        let n_groups = split.tiles.len();
+        split.tiles = std::mem::take(&mut split.tiles)
+            .into_par_iter()
+            .enumerate()
+            .map(|(group, group_tiles)| {
+                group_tiles
+                    .into_iter()
+                    .map(|tile| {
+                        if tile.gates.is_empty() {
+                            tile
+                        } else {
+                            self.run_oracle(tile, task_id * n_groups + group)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        tiles.join(split, segment.num_qubits)
+    }
+    // Runs the oracle on a window, tile by tile if tiled.
+    fn run_window(&self, segment: &CircuitLayer, task_id: usize) -> CircuitLayer {
+        let optimized = match &self.tiles {
+            Some(tiles) => self.run_tiles(tiles, segment.to_seq(), task_id),
+            None => self.run_oracle(segment.to_seq(), task_id),
+        };
+        CircuitLayer::from_seq(optimized, self.layout.clone())
+    }
+    // Optimizes the window starting at layer `left`, returning the updates that replace it if the
+    // oracle improved it.
+    fn optimize_window(
+        &self,
+        segment_before_optimize: CircuitLayer,
+        left: usize,
+        task_id: usize,
+    ) -> Option<Rewrite> {
+        // println!(
+        //     "segment_before_optimize: {:?},left: {:?}",
+        //     segment_before_optimize.cost(&self.config.cost),
+        //     left,
+        // );
+        let start = Instant::now();
+        let segment_after_optimize = self.run_window(&segment_before_optimize, task_id);
+        self.span(
+            "oracle",
+            PID_WORKERS,
+            rayon::current_thread_index().unwrap_or(task_id),
+            start,
+            json!({
+                "left": left,
+                "right": left + segment_before_optimize.len(),
+                "gates_before": segment_before_optimize.gate_count(),
+                "gates_after": segment_after_optimize.gate_count(),
+            }),
+        );
+        if !self.accepts(&segment_before_optimize, &segment_after_optimize) {
+            return None;
+        }
+        self.cost_decrease.fetch_add(
+            segment_before_optimize.cost(&self.config.cost) as isize
+                - segment_after_optimize.cost(&self.config.cost) as isize,
+            Ordering::Relaxed,
+        );
+        if segment_after_optimize.len() > segment_before_optimize.len() {
+            return Some(Rewrite::Splice(segment_after_optimize));
+        }
+        let mut tree_updates: Vec<(usize, isize)> = vec![];
+        let mut gate_updates: Vec<(usize, isize)> = vec![];
+        let mut circ_updates: Vec<(usize, Vec<Gate>)> = vec![];
+        for i in 0..segment_before_optimize.len() {
+            let gates_before = segment_before_optimize.layers[i].gates.len() as isize;
+            if i < segment_after_optimize.len() {
+                let gates = segment_after_optimize.get_one(i).clone();
+                if gates.len() as isize != gates_before {
+                    gate_updates.push((i + left, gates.len() as isize - gates_before));
+                }
+                circ_updates.push((i + left, gates));
+                if segment_before_optimize.is_empty(i) {
+                    tree_updates.push((i + left, 1));
+                }
+            } else {
+                circ_updates.push((i + left, vec![]));
+                if gates_before > 0 {
+                    gate_updates.push((i + left, -gates_before));
+                }
+                if !segment_before_optimize.is_empty(i) {
+                    tree_updates.push((i + left, -1));
+                }
+            }
+        }
+        Some(Rewrite::InPlace((tree_updates, gate_updates, circ_updates)))
+    }
+    // Whether the acceptance policy replaces the segment `before` with `after`.
+    fn accepts(&self, before: &CircuitLayer, after: &CircuitLayer) -> bool {
+        let costs = |circ: &CircuitLayer| -> Vec<usize> {
+            self.objectives()
+                .iter()
+                .map(|cost| circ.cost(cost))
+                .collect()
+        };
+        costs(after) < costs(before) && after.len() <= before.len() + self.growth_allowance()
+    }
+    // The costs compared by the acceptance policy, most significant first.
+    fn objectives(&self) -> &[Cost] {
+        match &self.config.acceptance {
+            Acceptance::Lexicographic(costs) => costs,
+            _ => std::slice::from_ref(&self.config.cost),
+        }
+    }
+    // How many layers more than its window an oracle result may have.
+    fn growth_allowance(&self) -> usize {
+        match self.config.acceptance {
+            Acceptance::Growth(layers) => layers,
+            _ => 0,
+        }
+    }
+    // The cost `circ` had before any window was improved.
+    fn initial_cost(&self, circ: &CircuitLayer) -> usize {
+        (circ.cost(&self.config.cost) as isize + self.cost_decrease.load(Ordering::Relaxed))
+            as usize
+    }
+    // The cost of the circuit, given its cost before any window was improved.
+    fn current_cost(&self, initial_cost: usize) -> usize {
+        (initial_cost as isize - self.cost_decrease.load(Ordering::Relaxed)) as usize
+    }
+    // The block of `2 * omega` non-empty layers (of `2 * gates` gates under
+    // `WindowPolicy::Gates`) that `seam` falls in. Seams two blocks apart have disjoint windows.
+    fn block_of(&self, seam: usize) -> usize {
+        match self.config.window {
+            WindowPolicy::Gates(gates) => self.gtree.prefix_sum(seam, 0) / (2 * gates.max(1)),
+            _ => self.ftree.prefix_sum(seam, 0) / (2 * self.omega),
+        }
+    }
+
+    // Selects the seams of the even or the odd blocks, whichever are more unless `even` says.
+    fn find_seams(&self, seams: &Vec<usize>, even: Option<bool>) -> (Vec<usize>, Vec<usize>) {
         // debug!("Finding seams: {:?}", seams);
         let len_seams = seams.len();
-        let two_omega = self.omega * 2;
         let selected_seams_1: Vec<bool> = (0..len_seams)
             .into_par_iter()
             .map(|i| {
-                if (i == 0
-                    || (self.id_of_non_empty_layer(seams[i]) / two_omega
-                        - self.id_of_non_empty_layer(seams[i - 1]) / two_omega
-                        > 0))
-                    && (self.id_of_non_empty_layer(seams[i]) / two_omega) % 2 == 0
-                {
-                    true
-                } else {
-                    false
-                }
+                (i == 0 || (self.block_of(seams[i]) - self.block_of(seams[i - 1]) > 0))
+                    && self.block_of(seams[i]).is_multiple_of(2)
             })
             .collect();
         let n_selected_seams_1 = selected_seams_1.par_iter().filter(|x| **x).count();
         let selected_seams_2: Vec<bool> = (0..len_seams)
             .into_par_iter()
             .map(|i| {
-                if (i == 0
-                    || (self.id_of_non_empty_layer(seams[i]) / two_omega
-                        - self.id_of_non_empty_layer(seams[i - 1]) / two_omega
-                        > 0))
-                    && (self.id_of_non_empty_layer(seams[i]) / two_omega) % 2 == 1
-                {
-                    true
-                } else {
-                    false
-                }
+                (i == 0 || (self.block_of(seams[i]) - self.block_of(seams[i - 1]) > 0))
+                    && !self.block_of(seams[i]).is_multiple_of(2)
             })
             .collect();
         let n_selected_seams_2 = selected_seams_2.par_iter().filter(|x| **x).count();
-        if n_selected_seams_1 > n_selected_seams_2 {
+        if even.unwrap_or(n_selected_seams_1 > n_selected_seams_2) {
             (
                 seams
                     .par_iter()
@@ -142,128 +783,1192 @@ impl SoamRunner {
 
     pub fn pair_and_optimize(&mut self, seams: Vec<usize>) -> Vec<usize> {
         // info!("new cycle");
-        let (selected_seams, remaining_seams) = self.find_seams(&seams);
+        let seams = if self.exploring() {
+            self.jitter_seams(seams)
+        } else {
+            seams
+        };
+        let initial_cost = self.initial_cost(&self.circ);
+        let mut round = RoundTrace {
+            round: self.n_round + 1,
+            n_seams: seams.len(),
+            omega: self.omega,
+            cost_before: self.current_cost(initial_cost),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let even = self.exploring().then(|| self.rng().below(2) == 0);
+        let (selected_seams, remaining_seams) = self.find_seams(&seams, even);
+        self.span(
+            "find_seams",
+            PID_ROUNDS,
+            0,
+            start,
+            json!({ "round": round.round }),
+        );
         // debug!("selected_seams: {:?}", selected_seams);
         // debug!("remaining_seams: {:?}", remaining_seams);
+        let radii: Vec<usize> = if self.exploring() {
+            let range = self.jitter_range();
+            (0..selected_seams.len())
+                .map(|_| {
+                    self.omega
+                        .saturating_sub(self.rng().below(range + 1))
+                        .max(1)
+                })
+                .collect()
+        } else {
+            vec![self.omega; selected_seams.len()]
+        };
         let tasks: Vec<(usize, usize)> = selected_seams
             .par_iter()
-            .map(|&seam| {
-                (
-                    self.reverse_id_of_non_empty_layer(
-                        self.id_of_non_empty_layer(seam).saturating_sub(self.omega),
-                    ),
-                    self.reverse_id_of_non_empty_layer(
-                        (self.id_of_non_empty_layer(seam) + self.omega).min(self.circ.len()),
-                    ),
+            .zip(radii.par_iter())
+            .map(|(&seam, &radius)| {
+                window_of(
+                    &self.ftree,
+                    &self.gtree,
+                    &shrunk(&self.config.window, radius, self.omega),
+                    seam,
+                    radius,
+                    self.circ.len(),
                 )
             })
             .collect();
         let now = std::time::Instant::now();
-        let ((new_seams, tree_updates), circ_updates): (
-            (Vec<Vec<usize>>, Vec<Vec<(usize, isize)>>),
-            Vec<Vec<(usize, Vec<Gate>)>>,
-        ) = tasks
+        let (updates, mut latencies): (Vec<Option<Rewrite>>, Vec<f32>) = tasks
             .par_iter()
             .enumerate()
             .map(|(task_id, task)| {
                 let (left, right) = task;
-                let segment_before_optimize = self.circ.get(*left, *right);
-                // println!(
-                //     "segment_before_optimize: {:?},left: {:?},right: {:?}",
-                //     segment_before_optimize.cost(&self.config.cost),
-                //     *left,
-                //     *right
-                // );
-                let segment_after_optimize = CircuitLayer::from_seq(
-                    self.oracle_runner
-                        .run_single(segment_before_optimize.to_seq(), task_id),
-                    self.layout.clone(),
-                );
-                if segment_after_optimize.cost(&self.config.cost)
-                    < segment_before_optimize.cost(&self.config.cost)
-                    && segment_after_optimize.len() <= segment_before_optimize.len()
-                {
-                    let mut tree_updates: Vec<(usize, isize)> = vec![];
-                    let mut circ_updates: Vec<(usize, Vec<Gate>)> = vec![];
-                    for i in 0..*right - *left {
-                        if i < segment_after_optimize.len() {
-                            circ_updates
-                                .push((i + *left, segment_after_optimize.get_one(i).clone()));
-                            if self.circ.is_empty(i + *left) {
-                                tree_updates.push((i + *left, 1));
-                            }
-                        } else {
-                            circ_updates.push((i + *left, vec![]));
-                            if !self.circ.is_empty(i + *left) {
-                                tree_updates.push((i + *left, -1));
-                            }
-                        }
-                    }
-
-                    ((vec![*left, *right - 1], tree_updates), circ_updates)
-                } else {
-                    ((vec![], vec![]), vec![])
-                }
+                let start = Instant::now();
+                let updates = self.optimize_window(self.circ.get(*left, *right), *left, task_id);
+                (updates, start.elapsed().as_secs_f32())
             })
             .unzip();
         let time_oracle = now.elapsed().as_secs_f32();
+        self.span(
+            "oracle",
+            PID_ROUNDS,
+            0,
+            now,
+            json!({ "round": round.round }),
+        );
         self.time_oracle += time_oracle;
         self.n_round += 1;
+        let mut in_place = Vec::new();
+        let mut splices = Vec::new();
+        let mut improved = Vec::new();
+        for (&(left, right), update) in tasks.iter().zip(updates) {
+            match update {
+                Some(Rewrite::InPlace(updates)) => {
+                    in_place.push(updates);
+                    improved.push((left, right, right - left));
+                }
+                Some(Rewrite::Splice(segment)) => {
+                    improved.push((left, right, segment.len()));
+                    splices.push((left, right, segment));
+                }
+                None => {}
+            }
+        }
+        // Layers after a grown window move right by the number of layers it gained.
+        let shift = |layer: usize| -> usize {
+            layer
+                + splices
+                    .iter()
+                    .filter(|(_, right, _)| *right <= layer)
+                    .map(|(left, right, segment)| segment.len() - (right - left))
+                    .sum::<usize>()
+        };
+        let new_seams: Vec<usize> = improved
+            .iter()
+            .flat_map(|&(left, _, len)| [shift(left), shift(left) + len - 1])
+            .collect();
+        let remaining_seams: Vec<usize> = if splices.is_empty() {
+            remaining_seams
+        } else {
+            remaining_seams.into_iter().map(shift).collect()
+        };
+        let (tree_updates, (gate_updates, circ_updates)): (Vec<_>, (Vec<_>, Vec<_>)) = in_place
+            .into_iter()
+            .map(|(tree, gates, circ)| (tree, (gates, circ)))
+            .unzip();
+        let now = Instant::now();
         let circ_updates: Vec<_> = circ_updates.into_par_iter().flatten().collect();
         self.circ.par_set(circ_updates);
+        round.time_par_set = now.elapsed().as_secs_f32();
+        self.span(
+            "par_set",
+            PID_ROUNDS,
+            0,
+            now,
+            json!({ "round": round.round }),
+        );
+        let now = Instant::now();
         let tree_updates: Vec<_> = tree_updates.into_par_iter().flatten().collect();
-        self.ftree.add_at_batch(tree_updates.clone());
-        let new_seams: Vec<usize> = new_seams.into_par_iter().flatten().collect();
+        self.ftree.add_at_batch(tree_updates);
+        let gate_updates: Vec<_> = gate_updates.into_par_iter().flatten().collect();
+        self.gtree.add_at_batch(gate_updates);
+        if !splices.is_empty() {
+            splice_counts(&mut self.ftree, &mut self.gtree, &splices);
+            self.circ.splice_many(splices);
+        }
+        round.time_add_at_batch = now.elapsed().as_secs_f32();
+        self.span(
+            "add_at_batch",
+            PID_ROUNDS,
+            0,
+            now,
+            json!({ "round": round.round }),
+        );
         // println!("new_seams: {:?}", new_seams);
         self.n_seams_total += new_seams.len();
+        round.n_selected = tasks.len();
+        round.n_improved = new_seams.len() / 2;
+        round.cost_after = self.current_cost(initial_cost);
+        round.set_oracle_latencies(&mut latencies);
+        self.trace.push(round);
         let new_seams: Vec<usize> = new_seams
             .iter()
             .merge(remaining_seams.iter())
-            .map(|&x| x)
+            .copied()
             .dedup()
             .collect();
         new_seams
     }
-    pub fn soam(&mut self) {
-        if self.config.use_soam {
-            let mut seams: Vec<usize> = (0..1 + (self.circ.len() / self.config.omega))
-                .map(|i| i * self.config.omega)
+    // Optimizes the windows as soon as a worker is free instead of in rounds. A window is
+    // dispatched if it does not overlap a window in flight, and its result is applied as soon as
+    // it returns; an improved window adds seams at both of its ends, as in `pair_and_optimize`,
+    // so the circuit is locally optimal when no seam is left. `n_rounds` counts the windows, and
+    // for the improvement budget a round is as many windows as there are workers.
+    fn soam_async(&mut self, seams: Vec<usize>) -> Vec<usize> {
+        let empty = CircuitLayer::new(vec![], self.circ.num_qubits, self.layout.clone());
+        let state = Mutex::new(AsyncState {
+            circ: std::mem::replace(&mut self.circ, empty),
+            ftree: std::mem::replace(&mut self.ftree, FenwickTree::from_iter(vec![])),
+            gtree: std::mem::replace(&mut self.gtree, FenwickTree::from_iter(vec![])),
+            pending: seams.into_iter().collect(),
+            in_flight: BTreeMap::new(),
+            stopped: false,
+            n_applied: 0,
+            round: RoundTrace::default(),
+            latencies: Vec::new(),
+            trace: Vec::new(),
+        });
+        let round_size = rayon::current_num_threads();
+        let initial_cost = self.initial_cost(&state.lock().unwrap().circ);
+        {
+            let mut state = state.lock().unwrap();
+            state.round = RoundTrace {
+                round: self.trace.len() + 1,
+                n_seams: state.pending.len(),
+                omega: self.omega,
+                cost_before: self.current_cost(initial_cost),
+                ..Default::default()
+            };
+        }
+        let changed = Condvar::new();
+        let n_windows = AtomicUsize::new(0);
+        let n_new_seams = AtomicUsize::new(0);
+        let now = std::time::Instant::now();
+        // Plain threads rather than rayon tasks: workers block on the condition variable, and
+        // the oracles may use the rayon pool themselves.
+        std::thread::scope(|scope| {
+            for task_id in 0..round_size {
+                let (this, state, changed) = (&*self, &state, &changed);
+                let (n_windows, n_new_seams) = (&n_windows, &n_new_seams);
+                scope.spawn(move || loop {
+                    let mut guard = state.lock().unwrap();
+                    let (seam, (left, right)) = loop {
+                        if !guard.stopped
+                            && this.out_of_budget(this.trace.len() + guard.trace.len(), None)
+                        {
+                            guard.stopped = true;
+                        }
+                        if guard.stopped {
+                            return;
+                        }
+                        if let Some(next) = guard.next_window(&this.config.window, this.omega) {
+                            break next;
+                        }
+                        if guard.pending.is_empty() && guard.in_flight.is_empty() {
+                            changed.notify_all();
+                            return;
+                        }
+                        guard = changed.wait(guard).unwrap();
+                    };
+                    guard.pending.remove(&seam);
+                    guard.in_flight.insert(left, (right, seam, task_id));
+                    let segment = guard.circ.get(left, right);
+                    drop(guard);
+                    n_windows.fetch_add(1, Ordering::Relaxed);
+
+                    let start = Instant::now();
+                    let updates = this
+                        .pool
+                        .install(|| this.optimize_window(segment, left, task_id));
+                    let latency = start.elapsed().as_secs_f32();
+                    let improved = updates.is_some();
+
+                    let mut guard = state.lock().unwrap();
+                    let state = &mut *guard;
+                    // earlier grown windows may have moved the window right
+                    let (moved, right) = state.window_of_worker(task_id);
+                    let offset = moved - left;
+                    let left = moved;
+                    match updates {
+                        Some(Rewrite::InPlace((tree_updates, gate_updates, circ_updates))) => {
+                            let shift = |updates: Vec<(usize, isize)>| -> Vec<(usize, isize)> {
+                                updates.into_iter().map(|(i, v)| (i + offset, v)).collect()
+                            };
+                            let now = Instant::now();
+                            state.circ.par_set(
+                                circ_updates
+                                    .into_iter()
+                                    .map(|(i, gates)| (i + offset, gates))
+                                    .collect(),
+                            );
+                            state.round.time_par_set += now.elapsed().as_secs_f32();
+                            this.span("par_set", PID_ROUNDS, task_id, now, json!({ "left": left }));
+                            let now = Instant::now();
+                            state.ftree.add_at_batch(shift(tree_updates));
+                            state.gtree.add_at_batch(shift(gate_updates));
+                            state.round.time_add_at_batch += now.elapsed().as_secs_f32();
+                            this.span(
+                                "add_at_batch",
+                                PID_ROUNDS,
+                                task_id,
+                                now,
+                                json!({ "left": left }),
+                            );
+                            state.pending.insert(left);
+                            state.pending.insert(right - 1);
+                        }
+                        // Only windows that grew under `Acceptance::Growth` are spliced. The
+                        // splice moves every layer after the window while the state is locked,
+                        // which stalls the other workers for O(n) time.
+                        Some(Rewrite::Splice(segment)) => {
+                            let len = segment.len();
+                            let now = Instant::now();
+                            let splices = vec![(left, right, segment)];
+                            splice_counts(&mut state.ftree, &mut state.gtree, &splices);
+                            state.circ.splice_many(splices);
+                            state.round.time_add_at_batch += now.elapsed().as_secs_f32();
+                            this.span("splice", PID_ROUNDS, task_id, now, json!({ "left": left }));
+                            state.shift(right, len - (right - left));
+                            state.pending.insert(left);
+                            state.pending.insert(left + len - 1);
+                        }
+                        None => {}
+                    }
+                    if improved {
+                        state.round.n_improved += 1;
+                        n_new_seams.fetch_add(2, Ordering::Relaxed);
+                    }
+                    state.in_flight.remove(&left);
+                    state.latencies.push(latency);
+                    state.round.n_selected += 1;
+                    state.n_applied += 1;
+                    if state.n_applied.is_multiple_of(round_size) {
+                        let cost = this.current_cost(initial_cost);
+                        let mut round = std::mem::replace(
+                            &mut state.round,
+                            RoundTrace {
+                                round: this.trace.len() + state.trace.len() + 2,
+                                n_seams: state.pending.len() + state.in_flight.len(),
+                                omega: this.omega,
+                                cost_before: cost,
+                                ..Default::default()
+                            },
+                        );
+                        round.cost_after = cost;
+                        round.set_oracle_latencies(&mut state.latencies);
+                        state.latencies.clear();
+                        let rounds = this.trace.len() + state.trace.len() + 1;
+                        if this.out_of_budget(rounds, Some(&round)) || this.level_dry(&round) {
+                            state.stopped = true;
+                        }
+                        state.trace.push(round);
+                    }
+                    let checkpoint = this.checkpoint_due(false).map(|path| {
+                        let checkpoint = Checkpoint {
+                            circ: state.circ.clone(),
+                            ftree: state.ftree.clone(),
+                            seams: state.seams(),
+                            n_rounds: this.n_rounds + n_windows.load(Ordering::Relaxed),
+                            n_seams_total: this.n_seams_total + n_new_seams.load(Ordering::Relaxed),
+                            time_oracle: this.time_oracle + now.elapsed().as_secs_f32(),
+                            trace: this.trace.iter().chain(&state.trace).cloned().collect(),
+                            ..this.checkpoint(vec![])
+                        };
+                        (path, checkpoint)
+                    });
+                    drop(guard);
+                    changed.notify_all();
+                    if let Some((path, checkpoint)) = checkpoint {
+                        Self::save_checkpoint(path, checkpoint);
+                    }
+                });
+            }
+        });
+        let mut state = state.into_inner().unwrap();
+        let seams = state.seams();
+        if state.round.n_selected > 0 {
+            state.round.cost_after = self.current_cost(initial_cost);
+            state.round.set_oracle_latencies(&mut state.latencies);
+            state.trace.push(state.round);
+        }
+        self.trace.extend(state.trace);
+        self.circ = state.circ;
+        self.ftree = state.ftree;
+        self.gtree = state.gtree;
+        self.time_oracle += now.elapsed().as_secs_f32();
+        self.n_rounds += n_windows.into_inner();
+        self.n_round = self.n_rounds;
+        self.n_seams_total += n_new_seams.into_inner();
+        seams
+    }
+    // Optimizes convex subcircuits of the gate DAG instead of layer slices. The window of an
+    // unoptimized gate is the convex hull of the gates at most `omega` edges away from it. A round
+    // optimizes up to one window per worker in parallel, on windows acting on disjoint qubits, so
+    // that splicing one back with `replace_gates_convex` leaves the others intact. The gates an
+    // improved window overlapped are unoptimized again, so the circuit is locally optimal when no
+    // gate is left. `seams` are the positions in the circuit of the unoptimized gates. As with the
+    // async scheduler, `n_rounds` counts the windows. Returns the positions of the gates still
+    // unoptimized if a budget stopped the run.
+    fn soam_dag(&mut self, seams: Vec<usize>) -> Vec<usize> {
+        let mut dag = CircuitDag::new_from_seq(self.circ.to_seq());
+        // the gates of a new DAG are numbered in circuit order, after its start and final nodes
+        let seams: BTreeSet<usize> = seams.into_iter().map(|seam| seam + 2).collect();
+        let optimized: Vec<usize> = dag
+            .graph
+            .unoptimized_gates()
+            .filter(|gate| !seams.contains(gate))
+            .collect();
+        for gate in optimized {
+            dag.graph.set_optimized(gate);
+        }
+        let round_size = rayon::current_num_threads();
+        let initial_cost = self.initial_cost(&self.circ);
+        let mut n_windows = 0;
+        let now = Instant::now();
+        while dag.graph.n_unoptimized() > 0 {
+            if self.out_of_budget(self.trace.len(), None) {
+                break;
+            }
+            let mut round = RoundTrace {
+                round: self.trace.len() + 1,
+                n_seams: dag.graph.n_unoptimized(),
+                cost_before: self.current_cost(initial_cost),
+                omega: self.omega,
+                ..Default::default()
+            };
+            let windows = dag_windows(&dag, self.omega, round_size);
+            let segments: Vec<CircuitSeq> = windows
+                .iter()
+                .map(|(_, indices)| CircuitSeq::new(dag.get_subgraph(indices), dag.num_qubits))
                 .collect();
-            self.n_seams_total = seams.len();
-            while !seams.is_empty() {
-                self.n_rounds += 1;
-                seams = self.pair_and_optimize(seams);
+            let this = &*self;
+            let results: Vec<(CircuitSeq, f32)> = segments
+                .par_iter()
+                .zip(windows.par_iter())
+                .enumerate()
+                .map(|(task_id, (segment, (seed, _)))| {
+                    let start = Instant::now();
+                    let optimized = this.run_oracle(segment.clone(), task_id);
+                    this.span(
+                        "oracle",
+                        PID_WORKERS,
+                        task_id,
+                        start,
+                        json!({
+                            "seed": seed,
+                            "gates_before": segment.gates.len(),
+                            "gates_after": optimized.gates.len(),
+                        }),
+                    );
+                    (optimized, start.elapsed().as_secs_f32())
+                })
+                .collect();
+            n_windows += windows.len();
+            round.n_selected = windows.len();
+            let mut latencies: Vec<f32> = results.iter().map(|(_, latency)| *latency).collect();
+            // the seeds of all windows are optimized before any improvement unoptimizes its
+            // neighborhood again
+            let mut improvements = vec![];
+            for (((seed, indices), segment), (optimized, _)) in
+                windows.into_iter().zip(segments).zip(results)
+            {
+                // the spliced gates may only act on the qubits that enter the window
+                let frontier = dag.get_frontier(indices.clone());
+                // the costs are measured on the layered windows, as sequences have no depth
+                let layered =
+                    |circ: &CircuitSeq| CircuitLayer::from_seq(circ.clone(), self.layout.clone());
+                let (segment, optimized_layer) = (layered(&segment), layered(&optimized));
+                let improved = self.accepts(&segment, &optimized_layer)
+                    && optimized
+                        .gates
+                        .iter()
+                        .all(|gate| gate.qubits().iter().all(|q| frontier.contains_key(q)));
+                dag.graph.set_optimized(seed);
+                if improved {
+                    self.cost_decrease.fetch_add(
+                        segment.cost(&self.config.cost) as isize
+                            - optimized_layer.cost(&self.config.cost) as isize,
+                        Ordering::Relaxed,
+                    );
+                    improvements.push((indices, optimized.gates));
+                }
+            }
+            round.n_improved = improvements.len();
+            for (indices, gates) in improvements {
+                let overlapping = dag.graph.get_neighborhood(&indices, self.omega);
+                dag.replace_gates_convex(indices, gates);
+                for idx in overlapping {
+                    dag.graph.set_unoptimized(idx);
+                }
+            }
+            round.cost_after = self.current_cost(initial_cost);
+            round.set_oracle_latencies(&mut latencies);
+            let stop =
+                self.out_of_budget(self.trace.len() + 1, Some(&round)) || self.level_dry(&round);
+            self.trace.push(round);
+            if let Some(path) = self.checkpoint_due(false) {
+                let circ = CircuitLayer::from_seq(dag.to_seq(), self.layout.clone());
+                let checkpoint = Checkpoint {
+                    ftree: FenwickTree::from_iter(vec![1; circ.len()]),
+                    circ,
+                    n_rounds: self.n_rounds + n_windows,
+                    time_oracle: self.time_oracle + now.elapsed().as_secs_f32(),
+                    ..self.checkpoint(unoptimized_positions(&dag))
+                };
+                Self::save_checkpoint(path, checkpoint);
+            }
+            if stop {
+                break;
+            }
+        }
+        let pending = unoptimized_positions(&dag);
+        self.circ = CircuitLayer::from_seq(dag.to_seq(), self.layout.clone());
+        self.ftree = FenwickTree::from_iter(vec![1; self.circ.len()]);
+        self.gtree = gate_counts(&self.circ);
+        self.time_oracle += now.elapsed().as_secs_f32();
+        self.n_rounds += n_windows;
+        self.n_round = self.n_rounds;
+        pending
+    }
+    // A random offset below `period` for the seams of a jittered run, 0 otherwise.
+    fn seam_offset(&mut self, period: usize) -> usize {
+        match &mut self.jitter {
+            Some(jitter) => jitter.rng.below(period),
+            None => 0,
+        }
+    }
+    // The seams of a resumed run, or one seam every `omega` layers of the current level. The DAG
+    // scheduler seeds its windows at every gate instead.
+    fn initial_seams(&mut self) -> Vec<usize> {
+        if let Some(seams) = self.resumed_seams.take() {
+            return seams;
+        }
+        let seams: Vec<usize> = match self.config.window {
+            _ if self.config.scheduler == Scheduler::Dag => (0..self.circ.gate_count()).collect(),
+            // one seam every `gates` gates, at the layer holding that gate
+            WindowPolicy::Gates(gates) => {
+                let gates = gates.max(1);
+                let n_gates = self.gtree.prefix_sum(self.gtree.len(), 0);
+                let offset = self.seam_offset(gates.min(n_gates + 1));
+                let mut seams: Vec<usize> = (0..1 + n_gates / gates)
+                    .map(|i| self.gtree.index_of(i * gates + offset + 1))
+                    .collect();
+                seams.dedup();
+                seams
             }
-            println!("Finished!");
-            println!("Number of rounds: {:?}", self.n_round);
-            println!("Oracle running time: {:?}", self.time_oracle);
+            _ => {
+                let offset = self.seam_offset(self.omega.min(self.circ.len() + 1));
+                (offset..=self.circ.len()).step_by(self.omega).collect()
+            }
+        };
+        self.n_seams_total += seams.len();
+        seams
+    }
+    fn soam_rounds(&mut self, mut seams: Vec<usize>) -> Vec<usize> {
+        while !seams.is_empty() {
+            self.n_rounds += 1;
+            seams = self.pair_and_optimize(seams);
+            if let Some(path) = self.checkpoint_due(false) {
+                Self::save_checkpoint(path, self.checkpoint(seams.clone()));
+            }
+            let round = self.trace.last();
+            if !seams.is_empty()
+                && (self.out_of_budget(self.trace.len(), round)
+                    || round.is_some_and(|round| self.level_dry(round)))
+            {
+                break;
+            }
+        }
+        seams
+    }
+    // Runs the scheduler from `seams`, returning the seams left when a budget stopped it. The seams
+    // of the DAG scheduler are the positions of its unoptimized gates.
+    fn schedule(&mut self, seams: Vec<usize>) -> Vec<usize> {
+        match self.config.scheduler {
+            Scheduler::Rounds => self.soam_rounds(seams),
+            Scheduler::Async => self.soam_async(seams),
+            Scheduler::Dag => self.soam_dag(seams),
+        }
+    }
+    // Runs the scheduler once per omega level. A level ends when the circuit is locally optimal
+    // for its omega or its improvements dried up; only a budget ends the run with seams pending
+    // at the last level. A `resumed` run continues its last level.
+    fn soam_levels(&mut self, mut seams: Vec<usize>, mut resumed: bool) -> Vec<usize> {
+        loop {
+            if !resumed || self.omega_levels.is_empty() {
+                self.omega_levels.push(OmegaLevel {
+                    omega: self.omega,
+                    first_round: self.n_rounds + 1,
+                    cost_before: self.circ.cost(&self.config.cost),
+                    start: self.elapsed(),
+                    ..Default::default()
+                });
+            }
+            resumed = false;
+            seams = loop {
+                seams = self.schedule(seams);
+                if !seams.is_empty() || !self.next_jitter_run() {
+                    break seams;
+                }
+                seams = self.initial_seams();
+            };
+            let (n_rounds, cost, elapsed) = (
+                self.n_rounds,
+                self.circ.cost(&self.config.cost),
+                self.elapsed(),
+            );
+            let level = self.omega_levels.last_mut().unwrap();
+            level.n_rounds = n_rounds + 1 - level.first_round;
+            level.cost_after = cost;
+            level.time = elapsed - level.start;
+            info!(
+                "Omega {}: cost {} -> {} in {} rounds, {:.2}s",
+                level.omega, level.cost_before, level.cost_after, level.n_rounds, level.time
+            );
+            if !seams.is_empty() && self.out_of_budget(self.trace.len(), self.trace.last()) {
+                return seams;
+            }
+            let Some(omega) = self.next_omega() else {
+                return seams;
+            };
+            self.omega = omega;
+            seams = self.initial_seams();
+        }
+    }
+    // Runs SOAM on the thread pool of the run.
+    pub fn soam(&mut self) {
+        let pool = self.pool.clone();
+        pool.install(|| self.soam_passes());
+    }
+    // Runs SOAM to convergence, then, with `multi_pass`, again on the circuit re-layered as late
+    // (ALAP) and as early (ASAP) as possible in turn, until a re-layered pass does not improve the
+    // cost. The first re-layered pass runs even if the first pass found nothing.
+    fn soam_passes(&mut self) {
+        self.started = Instant::now();
+        *self.last_checkpoint.lock().unwrap() = self.started;
+        if !self.config.use_soam {
+            self.circ =
+                CircuitLayer::from_seq(self.run_oracle(self.circ.to_seq(), 0), self.layout.clone());
+            return;
+        }
+        // a resumed run continues its last pass
+        let mut resumed = self.resumed_seams.is_some();
+        let mut seams = self.initial_seams();
+        loop {
+            if !resumed || self.passes.is_empty() {
+                let relayout = match self.passes.last() {
+                    None => Relayout::None,
+                    Some(pass) if pass.relayout == Relayout::Alap => Relayout::Asap,
+                    Some(_) => Relayout::Alap,
+                };
+                self.passes.push(PassTrace {
+                    pass: self.passes.len() + 1,
+                    relayout,
+                    first_round: self.n_rounds + 1,
+                    cost_before: self.circ.cost(&self.config.cost),
+                    start: self.elapsed(),
+                    ..Default::default()
+                });
+            }
+            seams = self.soam_levels(seams, resumed);
+            resumed = false;
+            let (n_rounds, cost, elapsed) = (
+                self.n_rounds,
+                self.circ.cost(&self.config.cost),
+                self.elapsed(),
+            );
+            let pass = self.passes.last_mut().unwrap();
+            pass.n_rounds = n_rounds + 1 - pass.first_round;
+            pass.cost_after = cost;
+            pass.time = elapsed - pass.start;
+            info!(
+                "Pass {} ({}): cost {} -> {} in {} rounds, {:.2}s",
+                pass.pass,
+                pass.relayout,
+                pass.cost_before,
+                pass.cost_after,
+                pass.n_rounds,
+                pass.time
+            );
+            let dry = pass.relayout != Relayout::None && pass.cost_after >= pass.cost_before;
+            if !seams.is_empty() || !self.config.multi_pass || dry {
+                break;
+            }
+            let asap = pass.relayout == Relayout::Alap;
+            self.circ = relayout(&self.circ, asap, &self.layout);
+            self.ftree = FenwickTree::from_iter(vec![1; self.circ.len()]);
+            self.gtree = gate_counts(&self.circ);
+            self.omega = self.config.omega;
+            seams = self.initial_seams();
+        }
+        if seams.is_empty() && self.config.certify != Certify::Off {
+            seams = self.certify();
+        }
+        info!("Finished!");
+        if self.config.scheduler != Scheduler::Rounds {
+            info!("Number of windows: {:?}", self.n_rounds);
         } else {
-            self.circ = CircuitLayer::from_seq(
-                self.oracle_runner.run_single(self.circ.to_seq(), 0),
-                self.layout.clone(),
+            info!("Number of rounds: {:?}", self.n_round);
+        }
+        info!("Oracle running time: {:?}", self.time_oracle);
+        self.n_seams_pending = seams.len();
+        self.converged = seams.is_empty();
+        if !self.converged {
+            info!("Stopped by budget with {} seams pending", seams.len());
+        }
+        // A final checkpoint lets a resumed sweep skip the finished configs, and continue the
+        // ones stopped by a budget.
+        if let Some(path) = self.checkpoint_due(true) {
+            Self::save_checkpoint(path, self.checkpoint(seams));
+        }
+    }
+    // Certifies the circuit, repairing the violations if configured to; see `Certify`. Returns
+    // the seams left if a budget stopped a repair.
+    fn certify(&mut self) -> Vec<usize> {
+        let mut n_repairs = 0;
+        let (n_windows, n_unchecked, violations, seams) = loop {
+            let (n_windows, n_unchecked, violations) = self.violations();
+            let repair = matches!(self.config.certify, Certify::Repair(max) if n_repairs < max);
+            if violations.is_empty() || !repair || n_unchecked > 0 {
+                break (n_windows, n_unchecked, violations, vec![]);
+            }
+            n_repairs += 1;
+            info!(
+                "Repair {}: {} violating windows",
+                n_repairs,
+                violations.len()
             );
+            let seams = match self.config.scheduler {
+                // the DAG scheduler seeds the gates of the violating windows
+                Scheduler::Dag => violations
+                    .iter()
+                    .flat_map(|violation| {
+                        self.gtree.prefix_sum(violation.start, 0)
+                            ..self.gtree.prefix_sum(violation.end, 0)
+                    })
+                    .collect::<BTreeSet<usize>>()
+                    .into_iter()
+                    .collect(),
+                _ => violations.iter().map(|violation| violation.seam).collect(),
+            };
+            let seams = self.schedule(seams);
+            if !seams.is_empty() {
+                break (n_windows, n_unchecked, violations, seams);
+            }
+        };
+        for violation in violations.iter() {
+            warn!(
+                "Violation at layers {}..{}: cost {} -> {}",
+                violation.start, violation.end, violation.cost_before, violation.cost_after
+            );
+        }
+        if n_unchecked > 0 {
+            warn!("The time limit left {} windows unchecked", n_unchecked);
         }
+        self.certificate = Some(Certificate {
+            passed: violations.is_empty() && n_unchecked == 0,
+            n_windows,
+            n_unchecked,
+            n_repairs,
+            violations,
+        });
+        seams
     }
-    pub fn correctness_check(&self, circ: &CircuitLayer) {
-        let len = circ.layers.len();
-        let correctness: Vec<bool> = (0..len.saturating_sub(self.omega))
-            .into_par_iter()
-            .map(|i| {
-                let slice = circ.get(i, i + self.omega);
-                let optimized_result = CircuitLayer::from_seq(
-                    self.oracle_runner.run_single(slice.to_seq(), i),
-                    self.layout.clone(),
+    // Runs the oracle on the window of every layer, returning the number of distinct windows, of
+    // those left unchecked once the time limit is reached, and those whose result the acceptance
+    // policy would take.
+    fn violations(&self) -> (usize, usize, Vec<Violation>) {
+        let len = self.circ.len();
+        let windows: Vec<(usize, (usize, usize))> = (0..=len)
+            .map(|seam| {
+                let window = window_of(
+                    &self.ftree,
+                    &self.gtree,
+                    &self.config.window,
+                    seam,
+                    self.omega,
+                    len,
                 );
-                optimized_result.cost(&self.config.cost) >= slice.cost(&self.config.cost)
+                (seam, window)
             })
+            .filter(|(_, (left, right))| left < right)
+            .dedup_by(|(_, window1), (_, window2)| window1 == window2)
             .collect();
-        // println!("correctness: {:?}", correctness);
-        let n_violations = correctness.iter().filter(|x| !**x).count();
-        if n_violations > 0 {
-            println!(
-                "Number of violations: {:?}",
-                correctness.iter().filter(|x| !**x).count()
+        let n_unchecked = AtomicUsize::new(0);
+        let violations = windows
+            .par_iter()
+            .enumerate()
+            .filter_map(|(task_id, &(seam, (left, right)))| {
+                if self.out_of_time() {
+                    n_unchecked.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                let before = self.circ.get(left, right);
+                let after = self.run_window(&before, task_id);
+                self.accepts(&before, &after).then(|| Violation {
+                    seam,
+                    start: left,
+                    end: right,
+                    cost_before: before.cost(&self.config.cost),
+                    cost_after: after.cost(&self.config.cost),
+                })
+            })
+            .collect();
+        (windows.len(), n_unchecked.into_inner(), violations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use circuit::config::{
+        AdaptiveOmega, Budget, Cost, Gateset, Jitter, PreprocessConfig, VoqcConfig,
+    };
+
+    // A runner of a VOQC config whose oracle returns every window unchanged.
+    fn identity_runner(budget: Budget) -> SoamRunner {
+        let config = SingleConfig {
+            circuit_path: "benchmarks/test.qasm".to_string(),
+            use_soam: true,
+            omega: 4,
+            oracle_name: OracleName::Voqc(VoqcConfig {
+                limits: Default::default(),
+            }),
+            preprocess_config: PreprocessConfig::None,
+            cost: Cost::Gate,
+            gateset: Gateset::Nam,
+            n_threads: 1,
+            layout: Layout::One,
+            scheduler: Scheduler::Rounds,
+            checkpoint: Default::default(),
+            budget,
+            timeline: false,
+            window: Default::default(),
+            adaptive: Default::default(),
+            tiling: Default::default(),
+            multi_pass: false,
+            jitter: Default::default(),
+            acceptance: Default::default(),
+            certify: Default::default(),
+            output: Default::default(),
+        };
+        SoamRunner::builder(config)
+            .oracle_runner(OracleEnum::Identity)
+            .build(CircuitSeq::new(vec![Gate::H(0); 16], 1))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_thread_pools() {
+        // every run gets the threads of its own config, whatever ran before it
+        for n_threads in [1, 3, 2] {
+            let mut config = identity_runner(Budget::default()).config;
+            config.n_threads = n_threads;
+            let mut runner = SoamRunner::builder(config)
+                .oracle_runner(OracleEnum::Identity)
+                .build(CircuitSeq::new(vec![Gate::H(0); 16], 1))
+                .unwrap();
+            assert_eq!(runner.pool.install(rayon::current_num_threads), n_threads);
+            runner.soam();
+            assert!(runner.converged);
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        let runner = identity_runner(Budget::default());
+        let round = |cost_before, cost_after| RoundTrace {
+            cost_before,
+            cost_after,
+            ..Default::default()
+        };
+        assert!(!runner.out_of_budget(1000, Some(&round(100, 100))));
+
+        let runner = identity_runner(Budget {
+            max_rounds: Some(3),
+            ..Default::default()
+        });
+        assert!(!runner.out_of_budget(2, None));
+        assert!(runner.out_of_budget(3, None));
+
+        let runner = identity_runner(Budget {
+            min_improvement: Some(0.01),
+            ..Default::default()
+        });
+        assert!(!runner.out_of_budget(1, None));
+        assert!(!runner.out_of_budget(1, Some(&round(100, 99))));
+        assert!(runner.out_of_budget(1, Some(&round(100, 100))));
+        assert!(!runner.out_of_budget(1, Some(&round(0, 0))));
+
+        let runner = identity_runner(Budget {
+            time_limit: Some(0.0),
+            ..Default::default()
+        });
+        assert!(runner.out_of_budget(0, None));
+    }
+    #[test]
+    fn test_dag_windows() {
+        let mut runner = identity_runner(Budget::default());
+        runner.config.scheduler = Scheduler::Dag;
+        runner.circ = CircuitLayer::from_seq(
+            CircuitSeq::new_from_source(
+                "OPENQASM 2.0;\nqreg q[3];\nh q[0];\ncx q[0], q[1];\nh q[2];\ncx q[1], q[2];\nx q[0];\n",
+            ),
+            Layout::One,
+        );
+        let gates = runner.circ.gate_count();
+        runner.soam();
+        // the oracle returns every window unchanged, so every gate is the seed of exactly one
+        // window
+        assert!(runner.converged);
+        assert_eq!(runner.n_rounds, gates);
+        assert_eq!(runner.circ.gate_count(), gates);
+        assert_eq!(
+            runner
+                .trace
+                .iter()
+                .map(|round| round.n_selected)
+                .sum::<usize>(),
+            gates
+        );
+
+        // windows on disjoint qubits share a round, and a resumed run only seeds the gates it
+        // left unoptimized
+        let mut config = runner.config.clone();
+        config.n_threads = 3;
+        config.omega = 1;
+        let source = "OPENQASM 2.0;\nqreg q[3];\nh q[0];\nh q[1];\nh q[2];\nx q[0];\n";
+        let mut runner = SoamRunner::builder(config.clone())
+            .oracle_runner(OracleEnum::Identity)
+            .build(CircuitSeq::new_from_source(source))
+            .unwrap();
+        runner.soam();
+        assert_eq!(runner.n_rounds, 4);
+        assert_eq!(runner.trace.len(), 2);
+        assert_eq!(runner.trace[0].n_selected, 3);
+        let mut runner = SoamRunner::builder(config.clone())
+            .oracle_runner(OracleEnum::Identity)
+            .build(CircuitSeq::new_from_source(source))
+            .unwrap();
+        let pending = runner.pool.clone().install(|| runner.soam_dag(vec![1, 3]));
+        assert!(pending.is_empty());
+        assert_eq!(runner.n_rounds, 2);
+
+        // `max_rounds` counts rounds, not windows
+        config.budget.max_rounds = Some(1);
+        let mut runner = SoamRunner::builder(config)
+            .oracle_runner(OracleEnum::Identity)
+            .build(CircuitSeq::new_from_source(source))
+            .unwrap();
+        runner.soam();
+        assert_eq!(runner.trace.len(), 1);
+        assert_eq!(runner.n_rounds, 3);
+        assert!(!runner.converged);
+    }
+    #[test]
+    fn test_dag_depth_costs() {
+        // depth costs are measured on layered windows
+        for (cost, acceptance) in [
+            (Cost::Depth, Acceptance::default()),
+            (Cost::Mixed, Acceptance::default()),
+            (
+                Cost::Gate,
+                Acceptance::Lexicographic(vec![Cost::Gate, Cost::Depth]),
+            ),
+        ] {
+            let mut runner = identity_runner(Budget::default());
+            runner.config.scheduler = Scheduler::Dag;
+            runner.config.cost = cost;
+            runner.config.acceptance = acceptance;
+            runner.layout = Layout::Dense;
+            runner.circ = CircuitLayer::from_seq(
+                CircuitSeq::new_from_source(
+                    "OPENQASM 2.0;\nqreg q[2];\nh q[0];\ncx q[0], q[1];\nx q[1];\n",
+                ),
+                Layout::Dense,
             );
+            runner.soam();
+            assert!(runner.converged);
+            assert_eq!(runner.circ.gate_count(), 3);
         }
     }
+    #[test]
+    fn test_relayout() {
+        let seq = CircuitSeq::new_from_source(
+            "OPENQASM 2.0;\nqreg q[3];\nh q[0];\nx q[2];\ncx q[0], q[1];\n",
+        );
+        let dense = CircuitLayer::from_seq(seq.clone(), Layout::Dense);
+        let alap = relayout(&dense, false, &Layout::Dense);
+        assert_eq!(alap.layers[0].gates, vec![Gate::H(0)]);
+        assert_eq!(alap.layers[1].gates.len(), 2);
+        let asap = relayout(&alap, true, &Layout::Dense);
+        assert_eq!(asap.layers[0].gates.len(), 2);
+
+        let one = CircuitLayer::from_seq(seq, Layout::One);
+        let alap = relayout(&one, false, &Layout::One);
+        assert_eq!(alap.len(), 3);
+        assert_eq!(alap.layers[2].gates, vec![Gate::X(2)]);
+    }
+    #[test]
+    fn test_passes_without_improvement() {
+        let mut runner = identity_runner(Budget::default());
+        runner.config.multi_pass = true;
+        runner.circ = CircuitLayer::from_seq(
+            CircuitSeq::new_from_source("OPENQASM 2.0;\nqreg q[2];\nh q[0];\ncx q[0], q[1];\n"),
+            Layout::One,
+        );
+        runner.ftree = FenwickTree::from_iter(vec![1; runner.circ.len()]);
+        runner.gtree = gate_counts(&runner.circ);
+        runner.soam();
+        // the oracle returns every window unchanged, so no pass improves the circuit, and the
+        // first re-layered pass is the last
+        assert_eq!(runner.passes.len(), 2);
+        assert_eq!(runner.passes[0].relayout, Relayout::None);
+        assert_eq!(runner.passes[1].relayout, Relayout::Alap);
+        assert!(runner
+            .passes
+            .iter()
+            .all(|pass| pass.cost_before == pass.cost_after));
+        assert!(runner.converged);
+    }
+    #[test]
+    fn test_jitter() {
+        let seeded = |seed| {
+            let mut runner = identity_runner(Budget::default());
+            runner.config.jitter = Jitter {
+                seed: Some(seed),
+                amount: 0.5,
+                restarts: 1,
+            };
+            runner.circ = CircuitLayer::new(vec![Gate::H(0); 40], 1, Layout::One);
+            runner.ftree = FenwickTree::from_iter(vec![1; 40]);
+            runner.gtree = gate_counts(&runner.circ);
+            runner.jitter = Some(JitterState {
+                rng: Rng::new(seed),
+                certifying: false,
+                restarts_left: 1,
+            });
+            runner
+        };
+        let mut runner = seeded(7);
+        let seams = runner.initial_seams();
+        assert!(seams[0] < 4);
+        assert!(seams.windows(2).all(|pair| pair[1] - pair[0] == 4));
+        assert_eq!(seeded(7).initial_seams(), seams);
+
+        let jittered = runner.jitter_seams(seams.clone());
+        assert!(jittered.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(jittered.iter().all(|seam| *seam < 40));
+
+        // explore, certify, restart, certify, done
+        assert!(runner.exploring());
+        assert!(runner.next_jitter_run());
+        assert!(!runner.exploring());
+        assert!(runner.next_jitter_run());
+        assert!(runner.exploring());
+        assert!(runner.next_jitter_run());
+        assert!(!runner.next_jitter_run());
+
+        let mut runner = seeded(7);
+        runner.soam();
+        assert!(runner.converged);
+        assert_eq!(runner.circ.gate_count(), 40);
+    }
+    #[test]
+    fn test_acceptance() {
+        let dense = |source: &str| {
+            CircuitLayer::from_seq(
+                CircuitSeq::new_from_source(&format!("OPENQASM 2.0;\nqreg q[4];\n{}", source)),
+                Layout::Dense,
+            )
+        };
+        let deep = dense("h q[0];\nh q[0];\nh q[0];\n");
+        // as many gates, in fewer layers
+        let shallow = dense("h q[0];\nh q[1];\nh q[1];\n");
+        let wide = dense("h q[0];\nh q[1];\nh q[2];\nh q[3];\n");
+        // fewer gates, in more layers
+        let narrow = dense("h q[0];\nh q[0];\n");
+
+        let mut runner = identity_runner(Budget::default());
+        assert!(!runner.accepts(&deep, &shallow));
+        assert!(!runner.accepts(&wide, &narrow));
+        runner.config.acceptance = Acceptance::Lexicographic(vec![Cost::Gate, Cost::Depth]);
+        assert!(runner.accepts(&deep, &shallow));
+        assert!(!runner.accepts(&shallow, &shallow));
+        runner.config.acceptance = Acceptance::Growth(1);
+        assert!(runner.accepts(&wide, &narrow));
+        runner.config.scheduler = Scheduler::Async;
+        assert!(runner.accepts(&wide, &narrow));
+        runner.config.acceptance = Acceptance::Growth(0);
+        assert!(!runner.accepts(&wide, &narrow));
+    }
+    #[test]
+    fn test_splice_windows() {
+        let mut circ = CircuitLayer::new(vec![Gate::H(0); 10], 1, Layout::One);
+        let mut ftree = FenwickTree::from_iter(vec![1; 10]);
+        let mut gtree = gate_counts(&circ);
+        let grown = CircuitLayer::new(vec![Gate::T(0); 3], 1, Layout::One);
+        let mut shrunk = CircuitLayer::new(vec![Gate::X(0); 2], 1, Layout::One);
+        shrunk.layers.push(Layer::new(vec![]));
+        let splices = vec![(2, 4, grown), (6, 9, shrunk)];
+        splice_counts(&mut ftree, &mut gtree, &splices);
+        circ.splice_many(splices);
+        assert_eq!(circ.len(), 11);
+        assert_eq!(circ.get_one(4), vec![Gate::T(0)]);
+        assert_eq!(circ.get_one(7), vec![Gate::X(0)]);
+        assert!(circ.is_empty(9));
+        assert_eq!(circ.get_one(10), vec![Gate::H(0)]);
+        let layers = circ.layers.iter().map(|layer| !layer.is_empty() as usize);
+        assert_eq!(ftree, FenwickTree::from_iter(layers));
+        assert_eq!(gtree, gate_counts(&circ));
+    }
+    #[test]
+    fn test_certify() {
+        let mut runner = identity_runner(Budget::default());
+        runner.config.certify = Certify::Repair(2);
+        // the oracle returns every window unchanged
+        assert!(runner.certify().is_empty());
+        let certificate = runner.certificate.take().unwrap();
+        assert!(certificate.passed);
+        assert_eq!(certificate.n_repairs, 0);
+        // one window per seam 0..=16
+        assert_eq!(certificate.n_windows, 17);
+        assert!(certificate.violations.is_empty());
+
+        // windows past the time limit are not checked, and the run does not pass
+        runner.config.budget.time_limit = Some(0.0);
+        assert!(runner.certify().is_empty());
+        let certificate = runner.certificate.unwrap();
+        assert!(!certificate.passed);
+        assert_eq!(certificate.n_unchecked, 17);
+    }
+    #[test]
+    fn test_adaptive_omega() {
+        let mut runner = identity_runner(Budget::default());
+        assert_eq!(runner.next_omega(), None);
+
+        runner.config.adaptive = AdaptiveOmega {
+            max_omega: Some(20),
+            growth: 3,
+            min_improvement: Some(0.01),
+        };
+        assert_eq!(runner.next_omega(), Some(12));
+        let round = RoundTrace {
+            cost_before: 100,
+            cost_after: 100,
+            ..Default::default()
+        };
+        assert!(runner.level_dry(&round));
+        runner.omega = 12;
+        assert_eq!(runner.next_omega(), Some(20));
+        // the last level runs to local optimality
+        runner.omega = 20;
+        assert_eq!(runner.next_omega(), None);
+        assert!(!runner.level_dry(&round));
+
+        // a growth of 1 still makes progress
+        runner.omega = 4;
+        runner.config.adaptive.growth = 1;
+        assert_eq!(runner.next_omega(), Some(5));
+        runner.config.window = WindowPolicy::Gates(100);
+        assert_eq!(runner.next_omega(), None);
+    }
+
+    #[test]
+    fn test_window_locks() {
+        let mut state = AsyncState {
+            circ: CircuitLayer::new(vec![Gate::H(0); 100], 1, Layout::One),
+            ftree: FenwickTree::from_iter(vec![1; 100]),
+            gtree: FenwickTree::from_iter(vec![1; 100]),
+            pending: [0, 10, 50].into_iter().collect(),
+            in_flight: BTreeMap::new(),
+            stopped: false,
+            n_applied: 0,
+            round: RoundTrace::default(),
+            latencies: Vec::new(),
+            trace: Vec::new(),
+        };
+        state.in_flight.insert(0, (20, 10, 0));
+        state.in_flight.insert(60, (70, 65, 1));
+        assert!(state.overlaps_in_flight(19, 30));
+        assert!(state.overlaps_in_flight(30, 61));
+        assert!(state.overlaps_in_flight(65, 66));
+        assert!(!state.overlaps_in_flight(20, 60));
+        assert!(!state.overlaps_in_flight(70, 80));
+        // the windows of seams 0 and 10 overlap [0, 20)
+        let window = window_of(
+            &state.ftree,
+            &state.gtree,
+            &WindowPolicy::Layers,
+            50,
+            4,
+            100,
+        );
+        assert_eq!(
+            state.next_window(&WindowPolicy::Layers, 4),
+            Some((50, window))
+        );
+        state.pending.remove(&50);
+        state.in_flight.insert(window.0, (window.1, 50, 2));
+        assert_eq!(state.next_window(&WindowPolicy::Layers, 4), None);
+        assert_eq!(state.seams(), vec![0, 10, 50, 65]);
+        // the window of worker 0 grew by 5 layers
+        state.shift(20, 5);
+        assert_eq!(state.seams(), vec![0, 10, 55, 70]);
+        assert_eq!(state.window_of_worker(1), (65, 75));
+        assert_eq!(state.window_of_worker(0), (0, 20));
+    }
+    #[test]
+    fn test_window_policies() {
+        let ftree = FenwickTree::from_iter(vec![1; 10]);
+        // layers 4 and 5 are dense, the rest hold a single gate
+        let gtree = FenwickTree::from_iter(vec![1, 1, 1, 1, 8, 8, 1, 1, 1, 1]);
+        let layers = window_of(&ftree, &gtree, &WindowPolicy::Layers, 5, 2, 10);
+        let gates = window_of(&ftree, &gtree, &WindowPolicy::Gates(8), 5, 2, 10);
+        let mixed = window_of(&ftree, &gtree, &WindowPolicy::Mixed(8), 5, 2, 10);
+        assert_eq!(layers, (2, 6));
+        // 8 gates on either side of the seam stop at the dense layers
+        assert_eq!(gates, (3, 5));
+        assert_eq!(mixed, (layers.0.max(gates.0), layers.1.min(gates.1)));
+        let wide = window_of(&ftree, &gtree, &WindowPolicy::Gates(12), 1, 2, 10);
+        assert_eq!(wide, (0, 5));
+
+        // a jittered window shrinks its gates with its layers
+        assert_eq!(
+            shrunk(&WindowPolicy::Gates(12), 2, 4),
+            WindowPolicy::Gates(6)
+        );
+        assert_eq!(
+            shrunk(&WindowPolicy::Mixed(3), 1, 4),
+            WindowPolicy::Mixed(1)
+        );
+        assert_eq!(shrunk(&WindowPolicy::Layers, 1, 4), WindowPolicy::Layers);
+    }
 }
@@ -0,0 +1,187 @@
+use circuit::{CircuitSeq, Gate};
+use std::collections::HashMap;
+
+// A partition of the qubits into groups, along which windows are cut into tiles.
+#[derive(Debug, Clone)]
+pub struct Tiles {
+    groups: Vec<Vec<usize>>,
+    // the group of every qubit, and its index in the group
+    group_of: Vec<usize>,
+    local: Vec<usize>,
+}
+
+impl Tiles {
+    // Groups the qubits of `circ` into groups of at most `max_qubits` qubits. Qubit pairs are
+    // merged in decreasing order of the number of gates acting on both of them, so that as few
+    // gates as possible cross two groups; groups without interactions are then packed together.
+    pub fn new(circ: &CircuitSeq, max_qubits: usize) -> Self {
+        let max_qubits = max_qubits.max(1);
+        let mut weights: HashMap<(usize, usize), usize> = HashMap::new();
+        for gate in circ.gates.iter() {
+            let qubits = gate.qubits();
+            for (i, &q1) in qubits.iter().enumerate() {
+                for &q2 in qubits[i + 1..].iter() {
+                    *weights.entry((q1.min(q2), q1.max(q2))).or_default() += 1;
+                }
+            }
+        }
+        let mut pairs: Vec<_> = weights.into_iter().collect();
+        pairs.sort_by(|(pair1, weight1), (pair2, weight2)| {
+            weight2.cmp(weight1).then(pair1.cmp(pair2))
+        });
+
+        // union-find over the qubits
+        let mut parent: Vec<usize> = (0..circ.num_qubits).collect();
+        let mut size = vec![1; circ.num_qubits];
+        fn root(parent: &mut [usize], mut q: usize) -> usize {
+            while parent[q] != q {
+                parent[q] = parent[parent[q]];
+                q = parent[q];
+            }
+            q
+        }
+        for ((q1, q2), _) in pairs {
+            let (r1, r2) = (root(&mut parent, q1), root(&mut parent, q2));
+            if r1 != r2 && size[r1] + size[r2] <= max_qubits {
+                parent[r2] = r1;
+                size[r1] += size[r2];
+            }
+        }
+        let mut components: HashMap<usize, Vec<usize>> = HashMap::new();
+        for q in 0..circ.num_qubits {
+            components.entry(root(&mut parent, q)).or_default().push(q);
+        }
+        let mut components: Vec<Vec<usize>> = components.into_values().collect();
+        components.sort_by(|c1, c2| c2.len().cmp(&c1.len()).then(c1[0].cmp(&c2[0])));
+
+        // first fit decreasing
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for component in components {
+            match groups
+                .iter_mut()
+                .find(|group| group.len() + component.len() <= max_qubits)
+            {
+                Some(group) => group.extend(component),
+                None => groups.push(component),
+            }
+        }
+        for group in groups.iter_mut() {
+            group.sort();
+        }
+        groups.sort();
+
+        let mut group_of = vec![0; circ.num_qubits];
+        let mut local = vec![0; circ.num_qubits];
+        for (g, group) in groups.iter().enumerate() {
+            for (i, &q) in group.iter().enumerate() {
+                group_of[q] = g;
+                local[q] = i;
+            }
+        }
+        Tiles {
+            groups,
+            group_of,
+            local,
+        }
+    }
+
+    pub fn groups(&self) -> &[Vec<usize>] {
+        &self.groups
+    }
+
+    // Splits a segment into tiles, on the qubits of a group only, and the gates acting on several
+    // groups (walls). Every wall ends the current tile of each group it acts on, so a group has
+    // one tile more than walls acting on it. A tile only has gates of its group between the walls
+    // around it, so it commutes with the gates of the other groups up to the next wall, and the
+    // segment is equivalent to its pieces joined in order.
+    pub fn split(&self, segment: &CircuitSeq) -> Split {
+        let empty = |group: &Vec<usize>| CircuitSeq::new(vec![], group.len());
+        let mut tiles: Vec<Vec<CircuitSeq>> =
+            self.groups.iter().map(|group| vec![empty(group)]).collect();
+        let mut pieces = Vec::new();
+        for gate in segment.gates.iter().filter(|gate| !matches!(gate, Gate::B)) {
+            let mut groups: Vec<usize> = gate.qubits().iter().map(|&q| self.group_of[q]).collect();
+            groups.sort();
+            groups.dedup();
+            if let [group] = groups[..] {
+                let tile = tiles[group].last_mut().unwrap();
+                tile.gates.push(gate.map_qubits(|q| self.local[q]));
+                continue;
+            }
+            for group in groups {
+                pieces.push(Piece::Tile(group, tiles[group].len() - 1));
+                tiles[group].push(empty(&self.groups[group]));
+            }
+            pieces.push(Piece::Wall(gate.clone()));
+        }
+        for (group, group_tiles) in tiles.iter().enumerate() {
+            pieces.push(Piece::Tile(group, group_tiles.len() - 1));
+        }
+        Split { tiles, pieces }
+    }
+
+    // The inverse of `split`, with the (optimized) tiles mapped back to their qubits.
+    pub fn join(&self, split: Split, num_qubits: usize) -> CircuitSeq {
+        let mut gates = Vec::new();
+        for piece in split.pieces {
+            match piece {
+                Piece::Tile(group, i) => gates.extend(
+                    split.tiles[group][i]
+                        .gates
+                        .iter()
+                        .map(|gate| gate.map_qubits(|q| self.groups[group][q])),
+                ),
+                Piece::Wall(gate) => gates.push(gate),
+            }
+        }
+        CircuitSeq::new(gates, num_qubits)
+    }
+}
+
+// A segment cut by `Tiles::split`.
+#[derive(Debug, Clone)]
+pub struct Split {
+    // the tiles of every group, in order
+    pub tiles: Vec<Vec<CircuitSeq>>,
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    // a group and the index of one of its tiles
+    Tile(usize, usize),
+    Wall(Gate),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::statevector::equivalent;
+
+    #[test]
+    fn test_tiles() {
+        let circ = CircuitSeq::new_from_source(
+            "OPENQASM 2.0;\nqreg q[5];\ncx q[0], q[1];\ncx q[0], q[1];\ncx q[2], q[3];\nh q[4];\ncx q[1], q[2];\nh q[0];\nh q[1];\nt q[2];\n",
+        );
+        let tiles = Tiles::new(&circ, 2);
+        assert_eq!(tiles.groups(), &[vec![0, 1], vec![2, 3], vec![4]]);
+
+        let split = tiles.split(&circ);
+        let n_tiles: Vec<usize> = split.tiles.iter().map(|tiles| tiles.len()).collect();
+        assert_eq!(n_tiles, vec![2, 2, 1]);
+        assert_eq!(split.tiles[0][0].gates.len(), 2);
+        assert_eq!(split.tiles[0][0].num_qubits, 2);
+        assert_eq!(split.tiles[1][0].gates, vec![Gate::CX { q1: 0, q2: 1 }]);
+        assert_eq!(split.tiles[2][0].gates, vec![Gate::H(0)]);
+        // the crossing gate is a wall between the tiles of groups 0 and 1, and the gates after it
+        // make their second tiles
+        assert_eq!(split.tiles[0][1].gates, vec![Gate::H(0), Gate::H(1)]);
+        assert_eq!(split.tiles[1][1].gates, vec![Gate::T(0)]);
+
+        let joined = tiles.join(split, circ.num_qubits);
+        assert_eq!(joined.gates.len(), circ.gates.len());
+        assert_eq!(joined.gates[2], Gate::CX { q1: 2, q2: 3 });
+        assert_eq!(joined.gates[3], Gate::CX { q1: 1, q2: 2 });
+        assert!(equivalent(&joined, &circ, 4, 0));
+    }
+}
@@ -0,0 +1,111 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+// Statistics of one SOAM round, written next to the results to plot convergence and find
+// straggling oracle calls. Times are in seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RoundTrace {
+    pub round: usize,
+    // seams pending at the start of the round, and how many of them were optimized
+    pub n_seams: usize,
+    pub n_selected: usize,
+    pub n_improved: usize,
+    pub cost_before: usize,
+    pub cost_after: usize,
+    pub oracle_min: f32,
+    pub oracle_median: f32,
+    pub oracle_max: f32,
+    pub time_par_set: f32,
+    pub time_add_at_batch: f32,
+    pub omega: usize,
+}
+
+// The contribution of one omega level. A run with a fixed omega has a single level. `start` and
+// `time` are in seconds, `start` counted from the start of the run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OmegaLevel {
+    pub omega: usize,
+    pub first_round: usize,
+    pub n_rounds: usize,
+    pub cost_before: usize,
+    pub cost_after: usize,
+    pub start: f32,
+    pub time: f32,
+}
+
+// How the circuit was re-layered before a pass.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Display)]
+pub enum Relayout {
+    // the first pass, on the circuit as given
+    #[default]
+    None,
+    // every gate as late as possible
+    Alap,
+    // every gate as early as possible
+    Asap,
+}
+
+// The contribution of one pass of a multi-pass run. A single-pass run has a single pass.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PassTrace {
+    pub pass: usize,
+    pub relayout: Relayout,
+    pub first_round: usize,
+    pub n_rounds: usize,
+    pub cost_before: usize,
+    pub cost_after: usize,
+    pub start: f32,
+    pub time: f32,
+}
+
+impl RoundTrace {
+    pub fn set_oracle_latencies(&mut self, latencies: &mut [f32]) {
+        if latencies.is_empty() {
+            return;
+        }
+        latencies.sort_by(f32::total_cmp);
+        self.oracle_min = latencies[0];
+        self.oracle_median = latencies[latencies.len() / 2];
+        self.oracle_max = latencies[latencies.len() - 1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oracle_latencies() {
+        let mut trace = RoundTrace::default();
+        trace.set_oracle_latencies(&mut []);
+        assert_eq!(trace, RoundTrace::default());
+        trace.set_oracle_latencies(&mut [0.3, 0.1, 5.0, 0.2, 0.4]);
+        assert_eq!(trace.oracle_min, 0.1);
+        assert_eq!(trace.oracle_median, 0.3);
+        assert_eq!(trace.oracle_max, 5.0);
+    }
+}
+
+// A window the oracle still improves after the run, as a range of layers. `seam` is the layer
+// whose window it is.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Violation {
+    pub seam: usize,
+    pub start: usize,
+    pub end: usize,
+    pub cost_before: usize,
+    pub cost_after: usize,
+}
+
+// The outcome of certifying a run, see `Certify`. `violations` are those left after the last
+// repair, and `n_unchecked` the windows the time limit left unchecked; a run only passes if every
+// window was checked.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Certificate {
+    pub passed: bool,
+    pub n_windows: usize,
+    #[serde(default)]
+    pub n_unchecked: usize,
+    pub n_repairs: usize,
+    pub violations: Vec<Violation>,
+}
@@ -55,7 +55,7 @@ pub fn parallel_scan(
         // println!("Padding to next power of 2: {} -> {}", n, next_power);
         let mut padded = Vec::with_capacity(next_power);
         padded.extend_from_slice(a);
-        padded.extend(std::iter::repeat(id).take(next_power - n));
+        padded.extend(std::iter::repeat_n(id, next_power - n));
 
         let (mut result, total) = parallel_scan_contract(f, id, &padded);
         result.truncate(n);
@@ -96,7 +96,7 @@ mod tests {
     fn test_parallel_scan_empty() {
         let input: Vec<usize> = vec![];
         let (result, total) = parallel_scan(|x, y| x + y, 0, &input);
-        assert_eq!(result, vec![]);
+        assert_eq!(result, Vec::<usize>::new());
         assert_eq!(total, 0);
     }
 
@@ -109,6 +109,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::inconsistent_digit_grouping)]
     fn benchmark_parallel_vs_sequential() {
         // Create a large input
         let large_input: Vec<usize> = (0..1_000_000_00).collect();
@@ -137,6 +138,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::inconsistent_digit_grouping)]
     fn benchmark_parallel_vs_sequential_2() {
         let large_input: Vec<usize> = (0..1_000_000_0).collect();
         let large_input2 = large_input.clone();
@@ -1,9 +1,206 @@
 use crate::oracles::quartz::quartz_impl::Quartz;
+use crate::utils::cleanup::{register_child, unregister_child, TempDir, TempFile};
+use crate::utils::timeline::Timeline;
 use crate::{
     oracles::qiskit::Qiskit, oracles::roqc_oracle::Roqc, oracles::tket::tket_impl::Tket,
     oracles::voqc::Voqc,
 };
-use circuit::CircuitSeq;
+use circuit::config::{Gateset, OracleName, SubprocessLimits};
+use circuit::{CircuitSeq, Gate};
+use derive_more::Display;
+use log::debug;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Display)]
+pub enum OracleError {
+    #[display("oracle request failed: {_0}")]
+    Request(String),
+    #[display("failed to parse oracle output: {_0}")]
+    Parse(String),
+    #[display("invalid oracle output: {_0}")]
+    Invalid(String),
+    #[display("oracle timed out after {_0}s")]
+    Timeout(f64),
+}
+
+// Counters shared by all the oracle calls of a run, updated from the rayon tasks.
+#[derive(Debug, Default)]
+pub struct OracleStats {
+    errors: AtomicUsize,
+    timeouts: AtomicUsize,
+}
+
+impl OracleStats {
+    pub fn record(&self, err: &OracleError) {
+        debug!("{}", err);
+        match err {
+            OracleError::Timeout(_) => self.timeouts.fetch_add(1, Ordering::Relaxed),
+            _ => self.errors.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+    pub fn timeouts(&self) -> usize {
+        self.timeouts.load(Ordering::Relaxed)
+    }
+}
+
+pub fn parse_output(output: &str) -> Result<CircuitSeq, OracleError> {
+    CircuitSeq::try_new_from_source(output).map_err(OracleError::Parse)
+}
+
+// Checks that `optimized` can replace `original` in the circuit: same qubit count, no gate
+// outside the qubits touched by `original`, and no gate kind outside the gate set (gate kinds
+// already present in `original` are allowed, since the oracle may leave them untouched).
+pub fn validate_output(
+    original: &CircuitSeq,
+    optimized: CircuitSeq,
+    gateset: &Gateset,
+) -> Result<CircuitSeq, OracleError> {
+    if optimized.num_qubits != original.num_qubits {
+        return Err(OracleError::Invalid(format!(
+            "expected {} qubits, got {}",
+            original.num_qubits, optimized.num_qubits
+        )));
+    }
+    let qubits: HashSet<usize> = original.gates.iter().flat_map(Gate::qubits).collect();
+    let kinds: HashSet<_> = original.gates.iter().map(std::mem::discriminant).collect();
+    for gate in optimized.gates.iter() {
+        if matches!(gate, Gate::B) {
+            return Err(OracleError::Invalid(
+                "unexpected placeholder gate".to_string(),
+            ));
+        }
+        if let Some(q) = gate.qubits().into_iter().find(|q| !qubits.contains(q)) {
+            return Err(OracleError::Invalid(format!(
+                "gate {} acts on qubit {} outside the segment",
+                gate, q
+            )));
+        }
+        if !gateset.contains(gate) && !kinds.contains(&std::mem::discriminant(gate)) {
+            return Err(OracleError::Invalid(format!(
+                "gate {} is not in the {} gate set",
+                gate, gateset
+            )));
+        }
+    }
+    Ok(optimized)
+}
+
+// The temporary directory of a file oracle.
+pub fn temp_dir() -> Result<TempDir, OracleError> {
+    TempDir::new().map_err(|err| {
+        OracleError::Request(format!("failed to create a temporary directory: {}", err))
+    })
+}
+
+// Runs an oracle executable that reads the circuit from `-f <file>` and writes the optimized
+// circuit to `-o <file>`, using per-task temporary files in `dir`, the directory of the oracle.
+pub fn run_file_oracle(
+    mut command: Command,
+    circ: &CircuitSeq,
+    dir: &Path,
+    task_id: usize,
+    limits: &SubprocessLimits,
+) -> Result<CircuitSeq, OracleError> {
+    let input = TempFile::new(dir.join(format!("temp_{}.qasm", task_id)));
+    let output = TempFile::new(dir.join(format!("temp_out_{}.qasm", task_id)));
+    command
+        .arg("-f")
+        .arg(input.path())
+        .arg("-o")
+        .arg(output.path());
+    std::fs::write(input.path(), circ.dump()).map_err(|err| {
+        OracleError::Request(format!("unable to write {:?}: {}", input.path(), err))
+    })?;
+    run_with_limits(command, limits)?;
+    let output_str = std::fs::read(output.path()).map_err(|err| {
+        OracleError::Request(format!("unable to read {:?}: {}", output.path(), err))
+    })?;
+    parse_output(&String::from_utf8_lossy(&output_str))
+}
+
+// Spawns `command` and waits for it, killing the child (and anything it spawned) once the
+// timeout expires. The memory limit is applied to the child's address space. A child that exits
+// with a failure status is an error.
+pub fn run_with_limits(mut command: Command, limits: &SubprocessLimits) -> Result<(), OracleError> {
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+        if let Some(memory_limit_mb) = limits.memory_limit_mb {
+            let bytes = memory_limit_mb.saturating_mul(1024 * 1024) as libc::rlim_t;
+            unsafe {
+                command.pre_exec(move || {
+                    let limit = libc::rlimit {
+                        rlim_cur: bytes,
+                        rlim_max: bytes,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|err| OracleError::Request(format!("failed to execute command: {}", err)))?;
+    register_child(child.id());
+    let result = wait_with_timeout(&mut child, limits.timeout);
+    unregister_child(child.id());
+    let status = result?;
+    if !status.success() {
+        return Err(OracleError::Request(format!(
+            "oracle failed with {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Option<f64>) -> Result<ExitStatus, OracleError> {
+    let Some(timeout) = timeout else {
+        return child
+            .wait()
+            .map_err(|err| OracleError::Request(format!("failed to wait for oracle: {}", err)));
+    };
+    let deadline = Instant::now() + Duration::from_secs_f64(timeout);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) if Instant::now() >= deadline => {
+                kill_child(child);
+                return Err(OracleError::Timeout(timeout));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+            Err(err) => {
+                kill_child(child);
+                return Err(OracleError::Request(format!(
+                    "failed to wait for oracle: {}",
+                    err
+                )));
+            }
+        }
+    }
+}
+
+fn kill_child(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
 
 pub enum OracleEnum {
     SyncQuartz(Quartz),
@@ -11,9 +208,28 @@ pub enum OracleEnum {
     Roqc(Roqc),
     Qiskit(Qiskit),
     Tket(Tket),
+    // returns every circuit unchanged, for tests that must not depend on an installed oracle
+    #[cfg(test)]
+    Identity,
 }
 
 impl OracleEnum {
+    // Starts the oracle of a config: Quartz starts its servers here, one per thread of the run
+    // unless its config sets the number, and the file oracles create their temporary directory.
+    pub fn new(oracle_name: &OracleName, n_threads: usize) -> Result<Self, OracleError> {
+        Ok(match oracle_name {
+            OracleName::Quartz(quartz_config) => OracleEnum::SyncQuartz(Quartz::new(
+                quartz_config.clone(),
+                quartz_config.n_threads.unwrap_or(n_threads),
+            )?),
+            OracleName::Voqc(voqc_config) => OracleEnum::Voqc(Voqc::new(voqc_config.clone())?),
+            OracleName::Roqc(roqc_config) => OracleEnum::Roqc(Roqc::new(roqc_config.clone())),
+            OracleName::Qiskit(qiskit_config) => {
+                OracleEnum::Qiskit(Qiskit::new(qiskit_config.clone())?)
+            }
+            OracleName::Tket(tket_config) => OracleEnum::Tket(Tket::new(tket_config.clone())?),
+        })
+    }
     // pub fn run(&self, circs: Vec<CircuitSeq>, function_name: String) -> Vec<CircuitSeq> {
     //     match self {
     //         OracleEnum::SyncQuartz(oracle) => oracle.run(circs, function_name),
@@ -21,13 +237,26 @@ impl OracleEnum {
     //         OracleEnum::Roqc(oracle) => oracle.run(circs),
     //     }
     // }
-    pub fn run_single(&self, circ: CircuitSeq, task_id: usize) -> CircuitSeq {
+    pub fn run_single(&self, circ: CircuitSeq, task_id: usize) -> Result<CircuitSeq, OracleError> {
         match self {
             OracleEnum::SyncQuartz(oracle) => oracle.run_single(circ, "optimize".to_string()),
             OracleEnum::Voqc(oracle) => oracle.run_single(circ, task_id),
-            OracleEnum::Roqc(oracle) => oracle.run_single(circ),
+            OracleEnum::Roqc(oracle) => Ok(oracle.run_single(circ)),
             OracleEnum::Qiskit(oracle) => oracle.run_single(circ, task_id),
             OracleEnum::Tket(oracle) => oracle.run_single(circ, task_id),
+            #[cfg(test)]
+            OracleEnum::Identity => Ok(circ),
+        }
+    }
+    pub fn set_timeline(&mut self, timeline: Arc<Timeline>) {
+        if let OracleEnum::SyncQuartz(oracle) = self {
+            oracle.set_timeline(timeline);
+        }
+    }
+    pub fn n_restarts(&self) -> usize {
+        match self {
+            OracleEnum::SyncQuartz(oracle) => oracle.n_restarts(),
+            _ => 0,
         }
     }
     pub fn shutdown(&self) {
@@ -37,6 +266,8 @@ impl OracleEnum {
             OracleEnum::Roqc(oracle) => oracle.shutdown(),
             OracleEnum::Qiskit(_) => {}
             OracleEnum::Tket(_) => {}
+            #[cfg(test)]
+            OracleEnum::Identity => {}
         }
     }
 
@@ -56,7 +287,7 @@ mod tests {
     use std::path;
 
     use super::*;
-    use circuit::config::{MultipleConfigs, OracleName};
+    use circuit::config::MultipleConfigs;
     use circuit::CircuitSeq;
     #[test]
     fn test_oracle_utils() {
@@ -65,33 +296,94 @@ mod tests {
         let config = MultipleConfigs::read_config(&"configs/test_quartz.toml".to_string())
             .to_single_configs()[0]
             .clone();
-        let oracle_runner = match config.oracle_name {
-            OracleName::Quartz(ref quartz_config) => {
-                let oracle_runner = Quartz::new(quartz_config.clone(), 12345);
-                OracleEnum::SyncQuartz(oracle_runner)
-            }
-            OracleName::Voqc(ref voqc_config) => {
-                let oracle_runner = Voqc::new(voqc_config.clone());
-                OracleEnum::Voqc(oracle_runner)
-            }
-            OracleName::Roqc(ref roqc_config) => {
-                let oracle_runner = Roqc::new(roqc_config.clone());
-                OracleEnum::Roqc(oracle_runner)
-            }
-            OracleName::Qiskit(ref qiskit_config) => {
-                let oracle_runner = Qiskit::new(qiskit_config.clone());
-                OracleEnum::Qiskit(oracle_runner)
-            }
-            OracleName::Tket(ref tket_config) => {
-                let oracle_runner = Tket::new(tket_config.clone());
-                OracleEnum::Tket(oracle_runner)
-            }
-        };
+        let oracle_runner = OracleEnum::new(&config.oracle_name, config.n_threads).unwrap();
         for _ in 0..10 {
-            let res = oracle_runner.run_single(circ.clone(), 0);
+            let res = oracle_runner.run_single(circ.clone(), 0).unwrap();
             println!("res: {:?}", res.len());
             println!("res: {:?}", res);
         }
         oracle_runner.shutdown();
     }
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_limits() {
+        let limits = SubprocessLimits {
+            timeout: Some(0.2),
+            memory_limit_mb: None,
+        };
+        let mut command = Command::new("sleep");
+        command.arg("10");
+        let now = Instant::now();
+        assert!(matches!(
+            run_with_limits(command, &limits),
+            Err(OracleError::Timeout(_))
+        ));
+        assert!(now.elapsed() < Duration::from_secs(5));
+
+        let mut command = Command::new("sleep");
+        command.arg("0");
+        assert!(run_with_limits(command, &limits).is_ok());
+
+        // a crashed oracle is an error, and a huge memory limit saturates instead of overflowing
+        assert!(matches!(
+            run_with_limits(Command::new("false"), &limits),
+            Err(OracleError::Request(_))
+        ));
+        let limits = SubprocessLimits {
+            timeout: None,
+            memory_limit_mb: Some(u64::MAX),
+        };
+        assert!(run_with_limits(Command::new("true"), &limits).is_ok());
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_run_file_oracle_concurrently() {
+        // an identity oracle: `sh -c .. -f <input> -o <output>` copies the input
+        let identity = || {
+            let mut command = Command::new("sh");
+            command.args(["-c", "cp \"$2\" \"$4\"", "sh"]);
+            command
+        };
+        let circs = [
+            CircuitSeq::new(vec![Gate::H(0), Gate::T(1)], 2),
+            CircuitSeq::new(vec![Gate::CX { q1: 1, q2: 0 }], 2),
+        ];
+        // two runs using the same task ids, as two configs of a concurrent sweep do
+        std::thread::scope(|scope| {
+            for circ in circs.iter() {
+                scope.spawn(move || {
+                    let dir = temp_dir().unwrap();
+                    for _ in 0..20 {
+                        let limits = SubprocessLimits::default();
+                        let out = run_file_oracle(identity(), circ, dir.path(), 0, &limits);
+                        assert_eq!(out.unwrap().gates, circ.gates);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_validate_output() {
+        let original = CircuitSeq::new_from_source(
+            "OPENQASM 2.0;\nqreg q[3];\nh q[0];\nh q[0];\ncx q[0], q[1];\n",
+        );
+        let valid = CircuitSeq::new_from_source("OPENQASM 2.0;\nqreg q[3];\ncx q[0], q[1];\n");
+        assert!(validate_output(&original, valid, &Gateset::Nam).is_ok());
+
+        let outside = CircuitSeq::new_from_source("OPENQASM 2.0;\nqreg q[3];\nh q[2];\n");
+        assert!(validate_output(&original, outside, &Gateset::Nam).is_err());
+
+        let not_in_gateset = CircuitSeq::new_from_source("OPENQASM 2.0;\nqreg q[3];\nt q[0];\n");
+        assert!(validate_output(&original, not_in_gateset, &Gateset::Nam).is_err());
+
+        let wrong_width = CircuitSeq::new_from_source("OPENQASM 2.0;\nqreg q[2];\nh q[0];\n");
+        assert!(validate_output(&original, wrong_width, &Gateset::Nam).is_err());
+
+        assert!(parse_output("An error occurred").is_err());
+        // gates on an undeclared register are refused rather than moved to qubit 0
+        assert!(parse_output("OPENQASM 2.0;\nqreg q[3];\nh r[0];\n").is_err());
+        let empty = parse_output("").unwrap();
+        assert!(validate_output(&original, empty, &Gateset::Nam).is_err());
+    }
 }
@@ -1,8 +1,12 @@
-use super::single_quartz::SingleQuartz;
+use super::single_quartz::{free_ports, SingleQuartz};
+use crate::oracles::oracle_utils::{parse_output, OracleError};
+use crate::utils::timeline::{Timeline, PID_QUARTZ};
 use circuit::config::QuartzConfig;
 use circuit::config::TimeOut;
 use circuit::CircuitSeq;
+use log::debug;
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
@@ -15,46 +19,57 @@ pub struct Quartz {
     server_status: Arc<Mutex<Vec<bool>>>,
     notify: Arc<Notify>,
     rt: tokio::runtime::Runtime,
+    // how many times a single request may respawn its server before giving up
+    max_restarts: usize,
+    n_restarts: AtomicUsize,
+    // slots whose server could not be restarted; they stay marked as busy
+    n_retired: AtomicUsize,
+    // records one span per request, on the track of the server slot that served it
+    timeline: Option<Arc<Timeline>>,
 }
 
 impl Quartz {
-    pub fn new(config: QuartzConfig, port: u16) -> Self {
-        let runners = (0..config.n_threads)
+    // Starts `n_servers` servers; fails if none of them starts.
+    pub fn new(config: QuartzConfig, n_servers: usize) -> Result<Self, OracleError> {
+        let runners = (0..n_servers)
             .map(|_| Arc::new(SingleQuartz::new()))
             .collect();
-        let status = vec![false; config.n_threads];
-        let rt = tokio::runtime::Runtime::new().unwrap();
+        let status = vec![false; n_servers];
+        let rt = tokio::runtime::Runtime::new()
+            .map_err(|err| OracleError::Request(format!("failed to build a runtime: {}", err)))?;
         let this = Quartz {
             servers: runners,
             server_status: Arc::new(Mutex::new(status)),
             notify: Arc::new(Notify::new()),
             rt,
+            max_restarts: config.max_restarts,
+            n_restarts: AtomicUsize::new(0),
+            n_retired: AtomicUsize::new(0),
+            timeline: None,
         };
         this.rt.block_on(async {
             this.initialize(
-                port,
                 config.gateset.to_string(),
                 config.ecc_path.to_string(),
                 config.cost.to_string(),
                 config.timeout.clone(),
             )
             .await
-        });
-        this
+        })?;
+        Ok(this)
     }
 
     pub async fn initialize(
         &self,
-        starting_port: u16,
         gate_set: String,
         ecc_file: String,
         cost_func: String,
         timeout: TimeOut,
-    ) {
+    ) -> Result<(), OracleError> {
         let mut futures = Vec::new();
 
-        for (i, runner) in self.servers.iter().enumerate() {
-            let port = starting_port + i as u16;
+        let ports = free_ports(self.servers.len())?;
+        for (runner, port) in self.servers.iter().zip(ports) {
             futures.push(runner.initialize(
                 port,
                 gate_set.clone(),
@@ -63,10 +78,89 @@ impl Quartz {
                 timeout.clone(),
             ));
         }
-        let _results = futures::future::join_all(futures).await;
+        let results = futures::future::join_all(futures).await;
+        for (i, result) in results.into_iter().enumerate() {
+            if let Err(err) = result {
+                debug!("Quartz server {} failed to start: {}", i, err);
+                let mut started = false;
+                for _ in 0..self.max_restarts {
+                    if self.respawn(&self.servers[i]).await.is_ok() {
+                        started = true;
+                        break;
+                    }
+                }
+                if !started {
+                    // Keep the slot marked as busy so that no request is ever sent to it.
+                    self.server_status.lock().await[i] = true;
+                    self.n_retired.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        if self.server_status.lock().await.iter().all(|&busy| busy) {
+            return Err(OracleError::Request(
+                "failed to start any Quartz server".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Restarts a server, on its previous port if possible and on a fresh port otherwise.
+    async fn respawn(&self, runner: &SingleQuartz) -> Result<(), OracleError> {
+        self.n_restarts.fetch_add(1, Ordering::Relaxed);
+        if let Some(port) = runner.port().await {
+            match runner.restart(port).await {
+                Ok(()) => return Ok(()),
+                Err(err) => debug!("failed to restart Quartz on port {}: {}", port, err),
+            }
+        }
+        let port = free_ports(1)?[0];
+        runner.restart(port).await
     }
 
-    pub async fn optimize_single_async(&self, circuit: String, function_name: String) -> String {
+    // Sends a request to `runner`, respawning it when it died or the request failed.
+    async fn optimize_with_restarts(
+        &self,
+        runner: &SingleQuartz,
+        circuit: String,
+        function_name: String,
+    ) -> Result<String, OracleError> {
+        let mut restarts = 0;
+        loop {
+            let result = if runner.is_alive().await {
+                runner
+                    .optimize(circuit.clone(), function_name.clone())
+                    .await
+            } else {
+                Err(OracleError::Request(
+                    "optimizer process is not running".to_string(),
+                ))
+            };
+            match result {
+                Err(OracleError::Request(err)) if restarts < self.max_restarts => {
+                    debug!("Quartz request failed, restarting the server: {}", err);
+                    restarts += 1;
+                    if let Err(err) = self.respawn(runner).await {
+                        debug!("{}", err);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn n_restarts(&self) -> usize {
+        self.n_restarts.load(Ordering::Relaxed)
+    }
+
+    pub fn set_timeline(&mut self, timeline: Arc<Timeline>) {
+        self.timeline = Some(timeline);
+    }
+
+    pub async fn optimize_single_async(
+        &self,
+        circuit: String,
+        function_name: String,
+    ) -> Result<String, OracleError> {
         loop {
             // Try to find an available runner
             let runner_index = {
@@ -83,7 +177,22 @@ impl Quartz {
             if let Some(idx) = runner_index {
                 // We found an available runner
                 let runner = &self.servers[idx];
-                let result = runner.optimize(circuit, function_name).await;
+                let start = std::time::Instant::now();
+                let result = self
+                    .optimize_with_restarts(runner, circuit, function_name)
+                    .await;
+                if let Some(timeline) = &self.timeline {
+                    let args = serde_json::json!({ "ok": result.is_ok() });
+                    timeline.span("request", "quartz", PID_QUARTZ, idx, start, args);
+                }
+
+                if result.is_err() && !runner.is_alive().await {
+                    // The restarts ran out, so the slot is kept busy like in `initialize`.
+                    debug!("Quartz server {} could not be restarted, giving it up", idx);
+                    self.n_retired.fetch_add(1, Ordering::Relaxed);
+                    self.notify.notify_one();
+                    return result;
+                }
 
                 // Mark the runner as available again
                 let mut status = self.server_status.lock().await;
@@ -95,18 +204,34 @@ impl Quartz {
                 return result;
             }
 
+            if self.n_retired.load(Ordering::Relaxed) == self.servers.len() {
+                // Wake the next waiter, which would otherwise wait forever as well.
+                self.notify.notify_one();
+                return Err(OracleError::Request(
+                    "no Quartz server is running".to_string(),
+                ));
+            }
+
             // If no runner is available, wait for notification
             self.notify.notified().await;
         }
     }
-    pub fn run_single(&self, circ: CircuitSeq, function_name: String) -> CircuitSeq {
+    pub fn run_single(
+        &self,
+        circ: CircuitSeq,
+        function_name: String,
+    ) -> Result<CircuitSeq, OracleError> {
         let circ_string = circ.dump();
         let res = self
             .rt
-            .block_on(async { self.optimize_single_async(circ_string, function_name).await });
-        CircuitSeq::new_from_source(&res)
+            .block_on(async { self.optimize_single_async(circ_string, function_name).await })?;
+        parse_output(&res)
     }
-    pub async fn optimize_all(&self, circuits: Vec<String>, function_name: String) -> Vec<String> {
+    pub async fn optimize_all(
+        &self,
+        circuits: Vec<String>,
+        function_name: String,
+    ) -> Vec<Result<String, OracleError>> {
         //A more advanced and efficient way to do job balancing
         let num_circuits = circuits.len();
         let mut results = vec![None; num_circuits];
@@ -160,6 +285,88 @@ impl Quartz {
     }
 }
 
+// The servers may still be referenced by tasks that were abandoned on a panic, so they are
+// killed here rather than when the last reference goes away.
+impl Drop for Quartz {
+    fn drop(&mut self) {
+        for runner in self.servers.iter() {
+            runner.kill();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Quartz whose servers exit right after they are spawned, so every start fails.
+    fn exiting_quartz(n_servers: usize, max_restarts: usize) -> Quartz {
+        Quartz {
+            servers: (0..n_servers)
+                .map(|_| Arc::new(SingleQuartz::with_command("false".to_string())))
+                .collect(),
+            server_status: Arc::new(Mutex::new(vec![false; n_servers])),
+            notify: Arc::new(Notify::new()),
+            rt: tokio::runtime::Runtime::new().unwrap(),
+            max_restarts,
+            n_restarts: AtomicUsize::new(0),
+            n_retired: AtomicUsize::new(0),
+            timeline: None,
+        }
+    }
+
+    #[test]
+    fn test_restarts() {
+        let quartz = exiting_quartz(2, 3);
+        let started = quartz.rt.block_on(quartz.initialize(
+            "Nam".to_string(),
+            "ecc.json".to_string(),
+            "Gate".to_string(),
+            TimeOut::PerSegment(1.0),
+        ));
+        assert!(started.is_err());
+        // each server is respawned at most max_restarts times before its slot is given up
+        assert_eq!(quartz.n_restarts(), 2 * 3);
+        assert!(quartz
+            .rt
+            .block_on(quartz.server_status.lock())
+            .iter()
+            .all(|&busy| busy));
+
+        let runner = Arc::clone(&quartz.servers[0]);
+        let result = quartz.rt.block_on(quartz.optimize_with_restarts(
+            &runner,
+            String::new(),
+            "optimize".to_string(),
+        ));
+        assert!(matches!(result, Err(OracleError::Request(_))));
+        assert_eq!(quartz.n_restarts(), 2 * 3 + 3);
+
+        let quartz = exiting_quartz(1, 0);
+        let runner = Arc::clone(&quartz.servers[0]);
+        let result = quartz.rt.block_on(quartz.optimize_with_restarts(
+            &runner,
+            String::new(),
+            "optimize".to_string(),
+        ));
+        assert!(result.is_err());
+        assert_eq!(quartz.n_restarts(), 0);
+
+        // a request gives up a slot whose server cannot be restarted, so the next request fails
+        // without respawning it again
+        let quartz = exiting_quartz(1, 2);
+        let optimize = || {
+            quartz
+                .rt
+                .block_on(quartz.optimize_single_async(String::new(), "optimize".to_string()))
+        };
+        assert!(optimize().is_err());
+        assert_eq!(quartz.n_restarts(), 2);
+        assert!(optimize().is_err());
+        assert_eq!(quartz.n_restarts(), 2);
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 
@@ -176,7 +383,6 @@ impl Quartz {
 //         rt.block_on(async {
 //             manager
 //                 .initialize(
-//                     12385,
 //                     "Nam_B".to_string(),
 //                     "resources/Nam_4_3_complete_ECC_set.json".to_string(),
 //                     "Gate".to_string(),
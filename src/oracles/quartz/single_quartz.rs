@@ -1,7 +1,6 @@
-use core::panic;
 use msgpack_rpc::Client;
 use std::env::consts;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, TcpListener};
 use std::process::{Child, Command};
 use std::sync::Arc;
 use std::time::Duration;
@@ -10,12 +9,45 @@ use tokio::sync::Mutex;
 use tokio::time::sleep;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 
+use crate::oracles::oracle_utils::OracleError;
+use crate::utils::cleanup::{register_child, unregister_child};
 use circuit::config::TimeOut;
 
+// Asks the OS for `n` distinct free ports. The listeners are all held until every port is
+// chosen, so the same port cannot be handed out twice; a port taken by someone else before the
+// server binds it makes the start fail, and the server is then respawned on another port.
+pub fn free_ports(n: usize) -> Result<Vec<u16>, OracleError> {
+    let listeners = (0..n)
+        .map(|_| TcpListener::bind("127.0.0.1:0"))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| OracleError::Request(format!("failed to find a free port: {}", err)))?;
+    listeners
+        .iter()
+        .map(|listener| {
+            listener
+                .local_addr()
+                .map(|addr| addr.port())
+                .map_err(|err| OracleError::Request(format!("failed to find a free port: {}", err)))
+        })
+        .collect()
+}
+
+// The arguments a server was started with, kept around so that it can be respawned.
+#[derive(Clone)]
+struct ServerArgs {
+    gate_set: String,
+    ecc_file: String,
+    cost_func: String,
+    timeout: TimeOut,
+}
+
 // This struct provides a single-threaded async interface to the Quartz optimizer.
 pub struct SingleQuartz {
+    // the server binary, replaced in tests by a stand-in
+    command: String,
     server: Arc<Mutex<Option<(u16, Child)>>>,
     client: Arc<Mutex<Option<Client>>>,
+    args: Arc<Mutex<Option<ServerArgs>>>,
 }
 
 impl Default for SingleQuartz {
@@ -26,9 +58,19 @@ impl Default for SingleQuartz {
 
 impl SingleQuartz {
     pub fn new() -> Self {
+        let command = match consts::OS {
+            "windows" => "./resources/quartz/build/Release/wrapper_rpc.exe",
+            _ => "./resources/quartz/build/wrapper_rpc",
+        };
+        Self::with_command(command.to_string())
+    }
+
+    pub fn with_command(command: String) -> Self {
         SingleQuartz {
+            command,
             server: Arc::new(Mutex::new(None)),
             client: Arc::new(Mutex::new(None)),
+            args: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -39,76 +81,141 @@ impl SingleQuartz {
         ecc_file: String,
         cost_func: String,
         timeout: TimeOut,
-    ) {
-        let timeout_type = match timeout {
+    ) -> Result<(), OracleError> {
+        *self.args.lock().await = Some(ServerArgs {
+            gate_set,
+            ecc_file,
+            cost_func,
+            timeout,
+        });
+        self.start(port).await
+    }
+
+    async fn start(&self, port: u16) -> Result<(), OracleError> {
+        let args = self
+            .args
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| OracleError::Request("server not initialized".to_string()))?;
+        let timeout_type = match args.timeout {
             TimeOut::PerSegment(_) => "PerSegment",
             TimeOut::PerGate(_) => "PerGate",
         };
-        let timeout_value = match timeout {
+        let timeout_value = match args.timeout {
             TimeOut::PerSegment(t) => t,
             TimeOut::PerGate(t) => t,
         };
-        let quartz_command = match consts::OS {
-            "windows" => "./resources/quartz/build/Release/wrapper_rpc.exe",
-            _ => "./resources/quartz/build/wrapper_rpc",
-        };
-        let child = Command::new(quartz_command)
+        let mut child = Command::new(&self.command)
             .arg(port.to_string())
-            .arg(gate_set)
-            .arg(ecc_file)
-            .arg(cost_func)
+            .arg(args.gate_set)
+            .arg(args.ecc_file)
+            .arg(args.cost_func)
             .arg(timeout_type)
             .arg(timeout_value.to_string())
             .spawn()
-            .expect("Failed to start optimizer process");
+            .map_err(|err| {
+                OracleError::Request(format!("failed to start optimizer process: {}", err))
+            })?;
+        register_child(child.id());
         let addr = format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap();
         let mut attempts = 0;
-        let my_socket: Option<TcpStream>;
-        loop {
+        let socket = loop {
             attempts += 1;
             match TcpStream::connect(&addr).await {
-                Ok(socket) => {
-                    my_socket = Some(socket);
-                    break;
-                }
-                Err(_) => {
-                    if attempts >= 20 {
-                        println!("Reached maximum number of connection attempts. Exiting.");
-                        panic!("Failed to connect to optimizer process");
+                Ok(socket) => break socket,
+                Err(err) => {
+                    // A server that already exited (e.g. the port is taken) will never answer.
+                    let exited = !matches!(child.try_wait(), Ok(None));
+                    if exited || attempts >= 20 {
+                        kill(child);
+                        return Err(OracleError::Request(format!(
+                            "failed to connect to optimizer process on port {}: {}",
+                            port, err
+                        )));
                     }
                     sleep(Duration::from_millis(100)).await;
                 }
             }
-        }
-        let client = Client::new(my_socket.unwrap().compat());
+        };
+        let client = Client::new(socket.compat());
 
         let mut server_lock = self.server.lock().await;
         *server_lock = Some((port, child));
 
         let mut client_lock = self.client.lock().await;
         *client_lock = Some(client);
+        Ok(())
     }
 
-    pub async fn optimize(&self, circuit: String, function_name: String) -> String {
+    // Kills the current server (if any) and starts a new one on `port`.
+    pub async fn restart(&self, port: u16) -> Result<(), OracleError> {
+        self.shutdown().await;
+        self.start(port).await
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        let mut server_lock = self.server.lock().await;
+        match server_lock.as_mut() {
+            Some((_port, child)) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    pub async fn port(&self) -> Option<u16> {
+        self.server.lock().await.as_ref().map(|(port, _)| *port)
+    }
+
+    pub async fn optimize(
+        &self,
+        circuit: String,
+        function_name: String,
+    ) -> Result<String, OracleError> {
         let client_lock = self.client.lock().await;
-        let client = client_lock.as_ref().expect("Client not initialized");
+        let client = client_lock
+            .as_ref()
+            .ok_or_else(|| OracleError::Request("client not initialized".to_string()))?;
 
-        client
+        let resp = client
             .request(function_name.as_str(), &[circuit.into()])
             .await
-            .map(|resp| resp.as_str().unwrap_or("").to_string())
-            .unwrap_or_else(|_| "An error occurred".to_string())
+            .map_err(|err| OracleError::Request(format!("{:?}", err)))?;
+        resp.as_str()
+            .map(|resp| resp.to_string())
+            .ok_or_else(|| OracleError::Request(format!("unexpected response {}", resp)))
     }
 
     pub async fn shutdown(&self) {
         let mut server_lock = self.server.lock().await;
-        if let Some((_port, mut child)) = server_lock.take() {
-            child.kill().expect("Failed to kill optimizer process");
+        if let Some((_port, child)) = server_lock.take() {
+            kill(child);
         }
 
         let mut client_lock = self.client.lock().await;
         *client_lock = None;
     }
+
+    // Synchronous version of `shutdown` for destructors. A server that is locked by an in-flight
+    // request is left alone; the interrupt handler still kills it through the child registry.
+    pub fn kill(&self) {
+        if let Ok(mut server_lock) = self.server.try_lock() {
+            if let Some((_port, child)) = server_lock.take() {
+                kill(child);
+            }
+        }
+    }
+}
+
+fn kill(mut child: Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+    unregister_child(child.id());
+}
+
+impl Drop for SingleQuartz {
+    fn drop(&mut self) {
+        self.kill();
+    }
 }
 
 #[cfg(test)]
@@ -125,13 +232,14 @@ mod tests {
         rt.block_on(async {
             quartz
                 .initialize(
-                    12375,
+                    free_ports(1).unwrap()[0],
                     "Nam_B".to_string(),
                     "resources/Nam_4_3_complete_ECC_set.json".to_string(),
                     "Gate".to_string(),
                     TimeOut::PerSegment(100.0),
                 )
                 .await
+                .expect("failed to start Quartz")
         });
         println!("Initialized");
         let result =
@@ -140,8 +248,17 @@ mod tests {
         rt.block_on(async { quartz.shutdown().await });
     }
     #[test]
+    fn test_free_ports() {
+        let ports = free_ports(16).unwrap();
+        let unique: std::collections::HashSet<_> = ports.iter().collect();
+        assert_eq!(unique.len(), ports.len());
+        assert!(ports
+            .iter()
+            .all(|port| TcpListener::bind(("127.0.0.1", *port)).is_ok()));
+    }
+    #[test]
     fn run_preprocess() {
-        for i in vec![18, 22, 26, 30] {
+        for i in [18, 22, 26, 30] {
             let circ: CircuitSeq = CircuitSeq::new_from_file(path::Path::new(
                 format!(
                     "benchmarks/vqe_new/vqe_n{}_from_python.qasm.preprocessed",
@@ -154,13 +271,14 @@ mod tests {
             rt.block_on(async {
                 quartz
                     .initialize(
-                        12376,
+                        free_ports(1).unwrap()[0],
                         "Nam_B".to_string(),
                         "resources/Nam_5_3_complete_ECC_set.json".to_string(),
                         "Gate".to_string(),
                         TimeOut::PerSegment(0.1),
                     )
                     .await
+                    .expect("failed to start Quartz")
             });
             println!("Initialized");
             let result = rt.block_on(async {
@@ -173,7 +291,7 @@ mod tests {
                 i
             ))
             .unwrap();
-            file.write_all(result.as_bytes()).unwrap();
+            file.write_all(result.unwrap().as_bytes()).unwrap();
             rt.block_on(async { quartz.shutdown().await });
         }
     }
@@ -1,6 +1,7 @@
 use circuit::{config::RoqcConfig, CircuitSeq};
 use rayon::prelude::*;
 use roqc::optimize_light;
+#[allow(dead_code)]
 pub struct Roqc {
     config: RoqcConfig,
 }
@@ -1,30 +1,31 @@
 use std::process::Command;
 
+use crate::oracles::oracle_utils::{run_file_oracle, temp_dir, OracleError};
+use crate::utils::cleanup::TempDir;
 use circuit::config::TketConfig;
 use circuit::CircuitSeq;
-#[allow(dead_code)]
 pub struct Tket {
     config: TketConfig,
+    // the temporary files of this oracle, apart from those of any other run
+    dir: TempDir,
 }
 
 impl Tket {
-    pub fn new(config: TketConfig) -> Self {
-        Tket { config }
+    pub fn new(config: TketConfig) -> Result<Self, OracleError> {
+        Ok(Tket {
+            config,
+            dir: temp_dir()?,
+        })
     }
-    pub fn run_single(&self, circ: CircuitSeq, task_id: usize) -> CircuitSeq {
-        let circ_str = circ.dump();
-        std::fs::write(format!("temp_{}.qasm", task_id), circ_str).expect("Unable to write file");
-        let _ = Command::new("/home/cc/miniconda3/envs/qiskit/bin/python")
-            .arg("/home/cc/quicr/soam/resources/tket/run_tket.py")
-            .args(["-f", &format!("temp_{}.qasm", task_id)])
-            .args(["-o", &format!("temp_out_{}.qasm", task_id)])
-            .output()
-            .expect("Failed to execute command");
-        let output_str =
-            std::fs::read(format!("temp_out_{}.qasm", task_id)).expect("Unable to read file");
-        let optimized_circ = CircuitSeq::new_from_source(&String::from_utf8_lossy(&output_str));
-        std::fs::remove_file(format!("temp_{}.qasm", task_id)).expect("Unable to remove file");
-        std::fs::remove_file(format!("temp_out_{}.qasm", task_id)).expect("Unable to remove file");
-        optimized_circ
+    pub fn run_single(&self, circ: CircuitSeq, task_id: usize) -> Result<CircuitSeq, OracleError> {
+        let mut command = Command::new("/home/cc/miniconda3/envs/qiskit/bin/python");
+        command.arg("/home/cc/quicr/soam/resources/tket/run_tket.py");
+        run_file_oracle(
+            command,
+            &circ,
+            self.dir.path(),
+            task_id,
+            &self.config.limits,
+        )
     }
 }
@@ -1,15 +1,21 @@
 use std::process::Command;
 
+use crate::oracles::oracle_utils::{run_file_oracle, temp_dir, OracleError};
+use crate::utils::cleanup::TempDir;
 use circuit::config::VoqcConfig;
 use circuit::CircuitSeq;
-#[allow(dead_code)]
 pub struct Voqc {
     config: VoqcConfig,
+    // the temporary files of this oracle, apart from those of any other run
+    dir: TempDir,
 }
 
 impl Voqc {
-    pub fn new(config: VoqcConfig) -> Self {
-        Voqc { config }
+    pub fn new(config: VoqcConfig) -> Result<Self, OracleError> {
+        Ok(Voqc {
+            config,
+            dir: temp_dir()?,
+        })
     }
     // pub fn run(&self, circs: Vec<CircuitSeq>) -> Vec<CircuitSeq> {
     //     let mut optimized_circs = vec![];
@@ -27,31 +33,25 @@ impl Voqc {
     //     }
     //     optimized_circs
     // }
-    pub fn run_single(&self, circ: CircuitSeq, task_id: usize) -> CircuitSeq {
-        let circ_str = circ.dump();
-        std::fs::write(format!("temp_{}.qasm", task_id), circ_str).expect("Unable to write file");
+    pub fn run_single(&self, circ: CircuitSeq, task_id: usize) -> Result<CircuitSeq, OracleError> {
         // check platform
         let platform = std::env::consts::OS;
-        if platform == "linux" {
-            let _ = Command::new("./resources/voqc/voqc_exec_linux")
-                .args(["-f", &format!("temp_{}.qasm", task_id)])
-                .args(["-o", &format!("temp_out_{}.qasm", task_id)])
-                .output()
-                .expect("Failed to execute command");
+        let command = if platform == "linux" {
+            Command::new("./resources/voqc/voqc_exec_linux")
         } else if platform == "macos" {
-            let _ = Command::new("./resources/voqc/voqc_exec_mac")
-                .args(["-f", &format!("temp_{}.qasm", task_id)])
-                .args(["-o", &format!("temp_out_{}.qasm", task_id)])
-                .output()
-                .expect("Failed to execute command");
+            Command::new("./resources/voqc/voqc_exec_mac")
         } else {
-            panic!("Unsupported platform: {}", platform);
-        }
-        let output_str =
-            std::fs::read(format!("temp_out_{}.qasm", task_id)).expect("Unable to read file");
-        let optimized_circ = CircuitSeq::new_from_source(&String::from_utf8_lossy(&output_str));
-        std::fs::remove_file(format!("temp_{}.qasm", task_id)).expect("Unable to remove file");
-        std::fs::remove_file(format!("temp_out_{}.qasm", task_id)).expect("Unable to remove file");
-        optimized_circ
+            return Err(OracleError::Request(format!(
+                "VOQC does not run on {}",
+                platform
+            )));
+        };
+        run_file_oracle(
+            command,
+            &circ,
+            self.dir.path(),
+            task_id,
+            &self.config.limits,
+        )
     }
 }
@@ -1,10 +1,14 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use circuit::config::SingleConfig;
-#[derive(Serialize, Deserialize, Debug)]
+use crate::optimizer::trace::{Certificate, OmegaLevel, PassTrace, RoundTrace};
+use circuit::config::{OutputConfig, OutputFormat, SingleConfig};
+use circuit::{CircuitSeq, Gate};
+use itertools::Itertools;
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SingleResult {
     pub original_depth: usize,
     pub optimized_depth: usize,
@@ -14,26 +18,293 @@ pub struct SingleResult {
     pub time: f32,
     pub oracle_time: f32,
     pub n_seams_total: usize,
+    #[serde(default)]
+    pub n_oracle_errors: usize,
+    #[serde(default)]
+    pub n_oracle_timeouts: usize,
+    #[serde(default)]
+    pub n_oracle_restarts: usize,
+    // false if a budget stopped the run before the circuit was locally optimal
+    #[serde(default = "default_converged")]
+    pub converged: bool,
+    #[serde(default)]
+    pub n_seams_pending: usize,
+    // what every omega level contributed, a single level unless omega is adaptive
+    #[serde(default)]
+    pub omega_levels: Vec<OmegaLevel>,
+    // what every pass contributed, a single pass unless `multi_pass` is set
+    #[serde(default)]
+    pub passes: Vec<PassTrace>,
+    // whether the run was certified locally optimal, if `certify` is on
+    #[serde(default)]
+    pub certificate: Option<Certificate>,
 }
-#[derive(Serialize, Deserialize, Debug)]
+
+// Results written before budgets existed always ran to convergence.
+fn default_converged() -> bool {
+    true
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConfigResult {
     pub config: SingleConfig,
     pub result: SingleResult,
+    // where the optimized circuit was written, if `output` is set
+    #[serde(default)]
+    pub output_path: Option<String>,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MultipleResults {
     pub results: Vec<ConfigResult>,
 }
-pub fn write_results(config_path: &str, results: &MultipleResults) {
+// The results of a sweep config are written to the same path under `results` instead of
+// `configs`.
+pub fn result_path(config_path: &str) -> String {
+    config_path.replace("configs", "results")
+}
+pub fn write_results(config_path: &str, results: &MultipleResults) -> Result<(), String> {
     //dump the config first, then some properties of the optimized circuit
-    let result_path = config_path.replace("configs", "results");
+    let result_path = result_path(config_path);
     let path = Path::new(&result_path);
 
     if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).expect("failed to create parent directory");
+        std::fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+
+    let contents = toml::to_string(results)
+        .map_err(|err| format!("failed to serialize the results: {}", err))?;
+    let mut file = File::create(path).map_err(|err| format!("{}: {}", result_path, err))?;
+    file.write_all(contents.as_bytes())
+        .map_err(|err| format!("{}: {}", result_path, err))
+}
+
+// The per-round trace of every config, written as `<results>_trace.csv` next to the results.
+// The `config` column is the index of the config in the results file.
+pub fn write_trace(config_path: &str, traces: &[Vec<RoundTrace>]) -> Result<(), String> {
+    let result_path = result_path(config_path);
+    let trace_path = Path::new(&result_path).with_extension("");
+    let trace_path = format!("{}_trace.csv", trace_path.display());
+    if let Some(parent) = Path::new(&trace_path).parent() {
+        std::fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut wtr =
+        csv::Writer::from_path(&trace_path).map_err(|err| format!("{}: {}", trace_path, err))?;
+    #[derive(Serialize)]
+    struct TraceConfig {
+        config: usize,
+    }
+    for (config, trace) in traces.iter().enumerate() {
+        for round in trace {
+            wtr.serialize((TraceConfig { config }, round))
+                .map_err(|err| format!("{}: {}", trace_path, err))?;
+        }
+    }
+    wtr.flush()
+        .map_err(|err| format!("{}: {}", trace_path, err))
+}
+
+// The Chrome trace of the `index`-th config, next to the results.
+pub fn timeline_path(config_path: &str, index: usize) -> String {
+    let result_path = result_path(config_path);
+    let stem = Path::new(&result_path).with_extension("");
+    format!("{}_timeline_{}.json", stem.display(), index)
+}
+
+// The path the optimized circuit of the `index`-th config is written to, if `output` is set.
+pub fn output_path(
+    output: &OutputConfig,
+    circuit_path: &str,
+    index: usize,
+) -> Result<Option<PathBuf>, String> {
+    let Some(dir) = &output.dir else {
+        return Ok(None);
+    };
+    let dir = shellexpand::env(dir)
+        .map_err(|err| format!("failed to expand {}: {}", dir, err))?
+        .into_owned();
+    let stem = Path::new(circuit_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = match output.format {
+        OutputFormat::Qasm => "qasm",
+        OutputFormat::Json => "json",
+    };
+    let name = output
+        .name
+        .replace("{stem}", &stem)
+        .replace("{index}", &index.to_string())
+        .replace("{ext}", ext);
+    Ok(Some(Path::new(&dir).join(name)))
+}
+
+// Checks that no two configs of a sweep write their optimized circuits to the same file.
+pub fn check_output_paths(configs: &[SingleConfig]) -> Result<(), String> {
+    let mut paths = HashMap::new();
+    for (index, config) in configs.iter().enumerate() {
+        let Some(path) = output_path(&config.output, &config.circuit_path, index)? else {
+            continue;
+        };
+        if let Some(first) = paths.insert(path.clone(), index) {
+            return Err(format!(
+                "configs {} and {} both write {}; use {{index}} in the output name",
+                first,
+                index,
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The format of a circuit file, from its extension.
+pub fn format_of(path: &Path) -> OutputFormat {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Qasm,
+    }
+}
+
+// Reads a circuit written in either output format.
+pub fn read_circuit(path: &Path) -> Result<CircuitSeq, String> {
+    let expanded = shellexpand::env(&path.to_string_lossy())
+        .map_err(|err| format!("failed to expand {}: {}", path.display(), err))?
+        .into_owned();
+    let source = std::fs::read_to_string(expanded)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    match format_of(path) {
+        OutputFormat::Qasm => CircuitSeq::try_new_from_source(&source),
+        OutputFormat::Json => {
+            #[derive(Deserialize)]
+            struct JsonCircuit {
+                num_qubits: usize,
+                gates: Vec<Gate>,
+            }
+            serde_json::from_str(&source)
+                .map(|circ: JsonCircuit| CircuitSeq::new(circ.gates, circ.num_qubits))
+                .map_err(|err| err.to_string())
+        }
+    }
+    .map_err(|err| format!("failed to parse {}: {}", path.display(), err))
+}
+
+// Writes the optimized circuit to `path`, rebased and formatted as `output` says.
+pub fn write_circuit(output: &OutputConfig, path: &Path, circ: &CircuitSeq) -> Result<(), String> {
+    let gates: Vec<Gate> = match &output.gateset {
+        Some(gateset) => circ
+            .gates
+            .iter()
+            .map(|gate| gateset.rebase(gate))
+            .flatten_ok()
+            .collect::<Result<_, _>>()?,
+        None => circ
+            .gates
+            .iter()
+            .filter(|gate| !matches!(gate, Gate::B))
+            .cloned()
+            .collect(),
+    };
+    let circ = CircuitSeq::new(gates, circ.num_qubits);
+    let contents = match output.format {
+        OutputFormat::Qasm => circ.dump(),
+        OutputFormat::Json => serde_json::json!({
+            "num_qubits": circ.num_qubits,
+            "gates": circ.gates,
+        })
+        .to_string(),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    std::fs::write(path, contents).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use circuit::config::MultipleConfigs;
+
+    #[test]
+    fn test_write_trace() {
+        let dir = std::env::temp_dir().join("soam_trace_test");
+        let config_path = dir.join("configs").join("sweep.toml");
+        let round = RoundTrace {
+            round: 1,
+            n_seams: 4,
+            cost_before: 10,
+            cost_after: 8,
+            ..Default::default()
+        };
+        write_trace(
+            config_path.to_str().unwrap(),
+            &[vec![round.clone()], vec![round.clone(), round]],
+        )
+        .unwrap();
+        let trace = std::fs::read_to_string(dir.join("results").join("sweep_trace.csv")).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("config,round,n_seams,"));
+        assert!(lines[3].starts_with("1,1,4,"));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 
-    let mut file = File::create(path).expect("failed to create file");
+    #[test]
+    fn test_write_circuit() {
+        let dir = std::env::temp_dir().join("soam_output_test");
+        let output = OutputConfig {
+            dir: Some(dir.to_str().unwrap().to_string()),
+            name: "{stem}_{index}.{ext}".to_string(),
+            gateset: Some(circuit::config::Gateset::Nam),
+            ..Default::default()
+        };
+        let path = output_path(&output, "benchmarks/adder.qasm", 3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, dir.join("adder_3.qasm"));
+        let circ =
+            CircuitSeq::new_from_source("OPENQASM 2.0;\nqreg q[2];\nt q[0];\ncx q[0], q[1];\n");
+        write_circuit(&output, &path, &circ).unwrap();
+        let written = CircuitSeq::new_from_file(&path);
+        assert_eq!(written.gates.len(), 2);
+        assert!(matches!(written.gates[0], Gate::RZ { q1: 0, .. }));
+        assert_eq!(written.gates[1], Gate::CX { q1: 0, q2: 1 });
 
-    let _ = file.write_all(toml::to_string(results).unwrap().as_bytes());
+        let json = dir.join("adder.json");
+        let output = OutputConfig {
+            format: format_of(&json),
+            ..Default::default()
+        };
+        write_circuit(&output, &json, &circ).unwrap();
+        assert_eq!(read_circuit(&json).unwrap().gates, circ.gates);
+        assert_eq!(read_circuit(&path).unwrap().gates, written.gates);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            output_path(&OutputConfig::default(), "adder.qasm", 0),
+            Ok(None)
+        );
+        let unset = OutputConfig {
+            dir: Some("$SOAM_UNSET_OUTPUT_DIR".to_string()),
+            ..Default::default()
+        };
+        assert!(output_path(&unset, "adder.qasm", 0).is_err());
+    }
+
+    #[test]
+    fn test_check_output_paths() {
+        let mut config = MultipleConfigs::read_config(&"configs/n64.toml".to_string());
+        config.circuit_path.truncate(1);
+        config.omega = vec![100, 200];
+        config.output = vec![OutputConfig {
+            dir: Some("out".to_string()),
+            ..Default::default()
+        }];
+        let mut configs = config.to_single_configs();
+        assert_eq!(configs.len(), 2);
+        // the default name tells the configs apart
+        assert!(check_output_paths(&configs).is_ok());
+        for config in configs.iter_mut() {
+            config.output.name = "{stem}.{ext}".to_string();
+        }
+        assert!(check_output_paths(&configs).is_err());
+    }
 }
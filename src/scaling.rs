@@ -0,0 +1,204 @@
+use crate::results::{result_path, ConfigResult};
+use circuit::config::{AllConfigKeys, Cost, MultipleConfigs, SingleConfig};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// The runs of a scaling experiment: `repetitions` runs of the single config of `config` for
+// every thread count. The repetitions are interleaved, so that a slow spell of the machine does
+// not fall on a single thread count.
+pub fn scaling_configs(
+    config: &MultipleConfigs,
+    repetitions: usize,
+) -> Result<Vec<SingleConfig>, String> {
+    let varying_only_threads = config
+        .unique_config_elements()
+        .into_iter()
+        .all(|(key, unique)| unique || key == AllConfigKeys::n_threads);
+    if !varying_only_threads {
+        return Err("a scaling config may only list several n_threads".to_string());
+    }
+    if config.n_threads.contains(&0) {
+        return Err("n_threads must be positive".to_string());
+    }
+    let configs = config.to_single_configs();
+    Ok((0..repetitions).flat_map(|_| configs.clone()).collect())
+}
+
+// The timings of one thread count. Speedups are relative to the medians of the smallest thread
+// count, and efficiencies scale them by the ratio of the thread counts.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScalingRow {
+    pub n_threads: usize,
+    pub n_runs: usize,
+    pub time_median: f32,
+    pub time_stddev: f32,
+    pub time_speedup: f32,
+    pub time_efficiency: f32,
+    pub oracle_time_median: f32,
+    pub oracle_time_stddev: f32,
+    pub oracle_time_speedup: f32,
+    pub oracle_time_efficiency: f32,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// The sample standard deviation, 0 for a single value.
+fn stddev(values: &[f32]) -> f32 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let square_sum: f32 = values.iter().map(|value| (value - mean).powi(2)).sum();
+    (square_sum / (values.len() - 1) as f32).sqrt()
+}
+
+fn optimized_cost(result: &ConfigResult) -> usize {
+    match result.config.cost {
+        Cost::Gate => result.result.optimized_gates,
+        Cost::Depth => result.result.optimized_depth,
+        Cost::Mixed => 10 * result.result.optimized_depth + result.result.optimized_gates,
+    }
+}
+
+// Summarizes the runs of a scaling experiment by thread count. Fails if the runs did not all
+// reach the same cost, as their timings would then measure different work.
+pub fn scaling_table(results: &[ConfigResult]) -> Result<Vec<ScalingRow>, String> {
+    let costs: Vec<usize> = results.iter().map(optimized_cost).collect();
+    if costs.iter().any(|cost| *cost != costs[0]) {
+        return Err(format!("the runs reached different costs: {:?}", costs));
+    }
+    let mut runs: BTreeMap<usize, (Vec<f32>, Vec<f32>)> = BTreeMap::new();
+    for result in results {
+        let (times, oracle_times) = runs.entry(result.config.n_threads).or_default();
+        times.push(result.result.time);
+        oracle_times.push(result.result.oracle_time);
+    }
+    let mut rows: Vec<ScalingRow> = runs
+        .into_iter()
+        .map(|(n_threads, (mut times, mut oracle_times))| ScalingRow {
+            n_threads,
+            n_runs: times.len(),
+            time_median: median(&mut times),
+            time_stddev: stddev(&times),
+            time_speedup: 1.0,
+            time_efficiency: 1.0,
+            oracle_time_median: median(&mut oracle_times),
+            oracle_time_stddev: stddev(&oracle_times),
+            oracle_time_speedup: 1.0,
+            oracle_time_efficiency: 1.0,
+        })
+        .collect();
+    let Some(base) = rows.first().cloned() else {
+        return Ok(rows);
+    };
+    for row in rows.iter_mut() {
+        let threads = row.n_threads as f32 / base.n_threads as f32;
+        row.time_speedup = base.time_median / row.time_median;
+        row.time_efficiency = row.time_speedup / threads;
+        row.oracle_time_speedup = base.oracle_time_median / row.oracle_time_median;
+        row.oracle_time_efficiency = row.oracle_time_speedup / threads;
+    }
+    Ok(rows)
+}
+
+// The scaling table of a config, written as `<results>_scaling.csv` next to the results.
+pub fn write_scaling(config_path: &str, rows: &[ScalingRow]) -> Result<String, String> {
+    let result_path = result_path(config_path);
+    let scaling_path = format!(
+        "{}_scaling.csv",
+        Path::new(&result_path).with_extension("").display()
+    );
+    if let Some(parent) = Path::new(&scaling_path).parent() {
+        std::fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut wtr = csv::Writer::from_path(&scaling_path)
+        .map_err(|err| format!("failed to create {}: {}", scaling_path, err))?;
+    for row in rows {
+        wtr.serialize(row).map_err(|err| err.to_string())?;
+    }
+    wtr.flush().map_err(|err| err.to_string())?;
+    Ok(scaling_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::SingleResult;
+    use crate::OptimizeOptions;
+    use circuit::config::{OracleName, RoqcConfig};
+
+    fn run(n_threads: usize, time: f32, optimized_gates: usize) -> ConfigResult {
+        let mut config = OptimizeOptions::new(OracleName::Roqc(RoqcConfig {})).config;
+        config.n_threads = n_threads;
+        ConfigResult {
+            config,
+            result: SingleResult {
+                original_depth: 0,
+                optimized_depth: 0,
+                original_gates: 10,
+                optimized_gates,
+                n_rounds: 1,
+                time,
+                oracle_time: time / 2.0,
+                n_seams_total: 1,
+                n_oracle_errors: 0,
+                n_oracle_timeouts: 0,
+                n_oracle_restarts: 0,
+                converged: true,
+                n_seams_pending: 0,
+                omega_levels: vec![],
+                passes: vec![],
+                certificate: None,
+            },
+            output_path: None,
+        }
+    }
+
+    #[test]
+    fn test_scaling_table() {
+        let results = vec![
+            run(1, 8.0, 5),
+            run(4, 2.5, 5),
+            run(1, 9.0, 5),
+            run(4, 3.5, 5),
+            run(1, 7.0, 5),
+            run(4, 3.0, 5),
+        ];
+        let rows = scaling_table(&results).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].n_threads, rows[0].n_runs), (1, 3));
+        assert_eq!(rows[0].time_median, 8.0);
+        assert_eq!(rows[0].time_stddev, 1.0);
+        assert_eq!(rows[0].time_speedup, 1.0);
+        assert_eq!(rows[1].time_median, 3.0);
+        assert!((rows[1].time_speedup - 8.0 / 3.0).abs() < 1e-6);
+        assert!((rows[1].time_efficiency - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(rows[1].oracle_time_median, 1.5);
+        assert!((rows[1].oracle_time_speedup - 8.0 / 3.0).abs() < 1e-6);
+
+        let diverged = vec![run(1, 8.0, 5), run(4, 2.0, 6)];
+        assert!(scaling_table(&diverged).is_err());
+    }
+
+    #[test]
+    fn test_scaling_configs() {
+        let mut config = MultipleConfigs::read_config(&"configs/n64.toml".to_string());
+        config.circuit_path.truncate(1);
+        config.n_threads = vec![1, 2, 4];
+        let configs = scaling_configs(&config, 2).unwrap();
+        let threads: Vec<usize> = configs.iter().map(|config| config.n_threads).collect();
+        assert_eq!(threads, vec![1, 2, 4, 1, 2, 4]);
+
+        config.omega = vec![100, 200];
+        assert!(scaling_configs(&config, 2).is_err());
+    }
+}
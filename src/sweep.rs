@@ -0,0 +1,239 @@
+use circuit::config::{OracleName, SingleConfig};
+use log::info;
+use std::sync::{Condvar, Mutex};
+
+// The threads and oracle servers the configs of a sweep share. A config needing more than the
+// whole budget runs alone with all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweepBudget {
+    pub threads: usize,
+    pub oracle_servers: usize,
+}
+
+impl SweepBudget {
+    // One config at a time, whatever it needs.
+    pub fn sequential() -> Self {
+        SweepBudget {
+            threads: 1,
+            oracle_servers: 1,
+        }
+    }
+    fn demand(&self, config: &SingleConfig) -> (usize, usize) {
+        (
+            config.n_threads.min(self.threads),
+            oracle_servers(config).min(self.oracle_servers),
+        )
+    }
+}
+
+// The oracle processes a run keeps busy: its Quartz servers, or one subprocess per thread for
+// the subprocess oracles. ROQC runs in process.
+pub fn oracle_servers(config: &SingleConfig) -> usize {
+    match &config.oracle_name {
+        OracleName::Quartz(quartz_config) => quartz_config.n_threads.unwrap_or(config.n_threads),
+        OracleName::Voqc(_) | OracleName::Qiskit(_) | OracleName::Tket(_) => config.n_threads,
+        OracleName::Roqc(_) => 0,
+    }
+}
+
+// Runs `run` on every config, starting them in order as soon as the budget has room for them,
+// and hands every result to `done` as its config finishes. Stops starting configs after the first
+// error, and returns it once the running configs finished.
+pub fn run_concurrently<T, E>(
+    configs: &[SingleConfig],
+    budget: SweepBudget,
+    run: impl Fn(usize, &SingleConfig) -> Result<T, E> + Sync,
+    done: impl FnMut(usize, T) + Send,
+) -> Result<(), E>
+where
+    T: Send,
+    E: Send,
+{
+    // the threads and servers in use, and the first error
+    let state: Mutex<(usize, usize, Option<E>)> = Mutex::new((0, 0, None));
+    let freed = Condvar::new();
+    let done = Mutex::new(done);
+    std::thread::scope(|scope| {
+        for (index, config) in configs.iter().enumerate() {
+            let (threads, servers) = budget.demand(config);
+            let mut guard = state.lock().unwrap();
+            while guard.2.is_none()
+                && (guard.0 + threads > budget.threads || guard.1 + servers > budget.oracle_servers)
+            {
+                guard = freed.wait(guard).unwrap();
+            }
+            if guard.2.is_some() {
+                break;
+            }
+            guard.0 += threads;
+            guard.1 += servers;
+            drop(guard);
+            info!("Starting config {} of {}", index + 1, configs.len());
+            let (run, state, freed, done) = (&run, &state, &freed, &done);
+            scope.spawn(move || {
+                let result = run(index, config);
+                let error = match result {
+                    Ok(result) => {
+                        (done.lock().unwrap())(index, result);
+                        None
+                    }
+                    Err(err) => Some(err),
+                };
+                let mut guard = state.lock().unwrap();
+                guard.0 -= threads;
+                guard.1 -= servers;
+                if guard.2.is_none() {
+                    guard.2 = error;
+                }
+                freed.notify_all();
+            });
+        }
+    });
+    match state.into_inner().unwrap().2 {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::optimize_with;
+    use crate::oracles::oracle_utils::OracleEnum;
+    use crate::utils::statevector::equivalent;
+    use crate::OptimizeOptions;
+    use circuit::config::{Cost, Gateset, QuartzConfig, RoqcConfig, TimeOut, VoqcConfig};
+    use circuit::CircuitSeq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn voqc(n_threads: usize) -> SingleConfig {
+        let mut config = OptimizeOptions::new(OracleName::Voqc(VoqcConfig {
+            limits: Default::default(),
+        }))
+        .config;
+        config.n_threads = n_threads;
+        config
+    }
+
+    #[test]
+    fn test_oracle_servers() {
+        assert_eq!(oracle_servers(&voqc(4)), 4);
+        let mut config = OptimizeOptions::new(OracleName::Roqc(RoqcConfig {})).config;
+        assert_eq!(oracle_servers(&config), 0);
+        let quartz = |n_threads| {
+            OracleName::Quartz(QuartzConfig {
+                cost: Cost::Gate,
+                timeout: TimeOut::PerGate(0.1),
+                ecc_path: "ecc_path".to_string(),
+                gateset: Gateset::Nam,
+                n_threads,
+                max_restarts: 3,
+            })
+        };
+        config.n_threads = 8;
+        config.oracle_name = quartz(None);
+        assert_eq!(oracle_servers(&config), 8);
+        config.oracle_name = quartz(Some(2));
+        assert_eq!(oracle_servers(&config), 2);
+    }
+
+    #[test]
+    fn test_run_concurrently() {
+        let configs: Vec<SingleConfig> = [2, 1, 3, 1, 2, 8].into_iter().map(voqc).collect();
+        let budget = SweepBudget {
+            threads: 4,
+            oracle_servers: 4,
+        };
+        let (in_use, peak, max_running) = (
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        );
+        let running = AtomicUsize::new(0);
+        let mut finished = Vec::new();
+        let result: Result<(), String> = run_concurrently(
+            &configs,
+            budget,
+            |index, config| {
+                let threads = config.n_threads.min(budget.threads);
+                peak.fetch_max(
+                    in_use.fetch_add(threads, Ordering::SeqCst) + threads,
+                    Ordering::SeqCst,
+                );
+                max_running.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                in_use.fetch_sub(threads, Ordering::SeqCst);
+                Ok(index)
+            },
+            |index, result| finished.push((index, result)),
+        );
+        assert!(result.is_ok());
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(max_running.load(Ordering::SeqCst) > 1);
+        finished.sort();
+        assert_eq!(
+            finished,
+            (0..6).map(|index| (index, index)).collect::<Vec<_>>()
+        );
+
+        // sequentially, and no config is started after an error
+        let started = AtomicUsize::new(0);
+        let result = run_concurrently(
+            &configs,
+            SweepBudget::sequential(),
+            |index, _| {
+                started.fetch_add(1, Ordering::SeqCst);
+                if index == 2 {
+                    Err(format!("config {} failed", index))
+                } else {
+                    Ok(())
+                }
+            },
+            |_, _| {},
+        );
+        assert_eq!(result, Err("config 2 failed".to_string()));
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_concurrent_runs() {
+        let circs = [
+            CircuitSeq::new_from_source(
+                "OPENQASM 2.0;\nqreg q[2];\nh q[0];\nh q[0];\ncx q[0], q[1];\nt q[1];\nt q[1];\n",
+            ),
+            CircuitSeq::new_from_source(
+                "OPENQASM 2.0;\nqreg q[2];\nx q[1];\ncx q[1], q[0];\nx q[1];\ntdg q[0];\n",
+            ),
+        ];
+        let configs: Vec<SingleConfig> = (0..4)
+            .map(|_| {
+                let mut config = voqc(1);
+                config.omega = 2;
+                config
+            })
+            .collect();
+        let budget = SweepBudget {
+            threads: 4,
+            oracle_servers: 4,
+        };
+        let outputs = Mutex::new(Vec::new());
+        let result = run_concurrently(
+            &configs,
+            budget,
+            |index, config| {
+                let opts = OptimizeOptions::from(config.clone());
+                optimize_with(circs[index % 2].clone(), opts, Some(OracleEnum::Identity))
+                    .map(|outcome| outcome.circuit)
+            },
+            |index, circuit| outputs.lock().unwrap().push((index, circuit)),
+        );
+        assert!(result.is_ok());
+        let outputs = outputs.into_inner().unwrap();
+        assert_eq!(outputs.len(), 4);
+        for (index, circuit) in outputs {
+            assert!(equivalent(&circuit, &circs[index % 2], 4, index as u64));
+        }
+    }
+}
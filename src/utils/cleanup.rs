@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Registry of the child processes and temporary files alive in this process. Destructors take
+// care of them on normal exits and panics, but not when the process is interrupted, so the
+// interrupt handler cleans up whatever is still registered here.
+static CHILDREN: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+static TEMP_FILES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+pub fn register_child(pid: u32) {
+    CHILDREN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(pid);
+}
+
+pub fn unregister_child(pid: u32) {
+    CHILDREN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&pid);
+}
+
+// A file that is removed when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        TEMP_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.clone());
+        TempFile { path }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        TEMP_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.path);
+    }
+}
+
+// A fresh directory under the system temporary directory, removed with its contents when
+// dropped. Every oracle instance writes its files to its own directory, so that the configs of a
+// concurrent sweep, or two processes sharing a working directory, never touch each other's files.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+static N_TEMP_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+impl TempDir {
+    pub fn new() -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "soam_{}_{}",
+            std::process::id(),
+            N_TEMP_DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path)?;
+        TEMP_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.clone());
+        Ok(TempDir { path })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+        TEMP_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.path);
+    }
+}
+
+// Kills every registered child (and its process group, if it leads one) and removes every
+// registered temporary file and directory.
+pub fn cleanup() {
+    let children = std::mem::take(&mut *CHILDREN.lock().unwrap_or_else(|e| e.into_inner()));
+    for pid in children {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+    let files = std::mem::take(&mut *TEMP_FILES.lock().unwrap_or_else(|e| e.into_inner()));
+    for file in files {
+        if file.is_dir() {
+            let _ = std::fs::remove_dir_all(file);
+        } else {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+// Cleans up and exits with status 130 on Ctrl-C (and SIGTERM on unix).
+pub fn install_interrupt_handler() {
+    std::thread::spawn(|| {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build signal runtime");
+        rt.block_on(async {
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{signal, SignalKind};
+                let mut terminate =
+                    signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            #[cfg(not(unix))]
+            let _ = tokio::signal::ctrl_c().await;
+        });
+        eprintln!("Interrupted, cleaning up");
+        cleanup();
+        std::process::exit(130);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_file() {
+        let file = TempFile::new("temp_cleanup_test.qasm");
+        std::fs::write(file.path(), "OPENQASM 2.0;").unwrap();
+        let path = file.path().to_path_buf();
+        assert!(path.exists());
+        drop(file);
+        assert!(!path.exists());
+        assert!(!TEMP_FILES.lock().unwrap().contains(&path));
+    }
+
+    #[test]
+    fn test_temp_dir() {
+        let (first, second) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        assert_ne!(first.path(), second.path());
+        let file = first.path().join("temp_0.qasm");
+        std::fs::write(&file, "OPENQASM 2.0;").unwrap();
+        let path = first.path().to_path_buf();
+        drop(first);
+        assert!(!path.exists() && !file.exists());
+        assert!(second.path().exists());
+    }
+}
@@ -1,8 +1,10 @@
 extern crate alloc;
 use alloc::vec::Vec;
+use core::ops::Range;
 use dashmap::DashMap;
 use rayon::prelude::*;
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+use serde::{Deserialize, Serialize};
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct FenwickTree {
     pub inner: Vec<usize>,
 }
@@ -13,7 +15,7 @@ impl FromIterator<usize> for FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let lengths: [usize; 5] = [1, 6, 3, 9, 2];
     /// // This is how lengths fenwick tree will look like internally
@@ -75,7 +77,7 @@ impl FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let lengths = [1, 6, 3, 9, 2];
     /// let fenwick_array = FenwickTree::from_iter(lengths);
@@ -106,7 +108,7 @@ impl FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let lengths = [1, 6, 3, 9, 2];
     /// let mut fenwick_array = FenwickTree::from_iter(lengths);
@@ -178,7 +180,7 @@ impl FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let mut fenwick_array = FenwickTree::from_iter([1, 6, 3].into_iter());
     /// fenwick_array.push(9);
@@ -190,15 +192,93 @@ impl FenwickTree {
     /// assert_eq!(fenwick_array.prefix_sum(4, 0), 19); // sum of [1, 6, 3, 9]
     /// ```
     pub fn push(&mut self, value: usize) {
-        let index = self.inner.len();
-        self.inner.push(value);
-
-        for i in 0..index {
+        self.insert(self.len(), value);
+    }
+    /// Inserts a value before the given index, shifting the values after it to the right.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soam::utils::ftree::FenwickTree;
+    ///
+    /// let mut fenwick_array = FenwickTree::from_iter([1, 6, 9]);
+    /// fenwick_array.insert(2, 3);
+    ///
+    /// assert_eq!(fenwick_array, FenwickTree::from_iter([1, 6, 3, 9]));
+    /// ```
+    pub fn insert(&mut self, index: usize, value: usize) {
+        self.splice(index..index, [value]);
+    }
+    /// Replaces the values in `range` with `values`, which may be more or fewer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soam::utils::ftree::FenwickTree;
+    ///
+    /// let mut fenwick_array = FenwickTree::from_iter([1, 6, 3, 9, 2]);
+    /// fenwick_array.splice(1..3, [4, 4, 4]);
+    ///
+    /// assert_eq!(fenwick_array, FenwickTree::from_iter([1, 4, 4, 4, 9, 2]));
+    /// ```
+    pub fn splice<I>(&mut self, range: Range<usize>, values: I)
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        self.splice_many(vec![(range, values.into_iter().collect())]);
+    }
+    /// Applies several splices at once; the ranges must be sorted and disjoint, and index the
+    /// tree before any of them is applied.
+    ///
+    /// Only the nodes from the start of the first range on are rebuilt, so splices near the end
+    /// are cheap: pushing a value takes `O(log n)`.
+    pub fn splice_many(&mut self, splices: Vec<(Range<usize>, Vec<usize>)>) {
+        let Some((first, _)) = splices.first() else {
+            return;
+        };
+        let start = first.start;
+        assert!(splices.last().unwrap().0.end <= self.inner.len());
+        // the nodes before `start` whose parents are at or after it, the same nodes that
+        // `prefix_sum(start)` visits
+        let mut crossing = Vec::new();
+        let mut current_idx = start;
+        while current_idx > 0 {
+            crossing.push(current_idx - 1);
+            current_idx &= current_idx - 1;
+        }
+        let add_to_parent = |inner: &mut Vec<usize>, i: usize, add: bool| {
             let parent = i | (i + 1);
-            let parent_val = self.inner[i];
-            if parent == index {
-                self.inner[index] += parent_val;
+            if parent < inner.len() {
+                if add {
+                    inner[parent] += inner[i];
+                } else {
+                    inner[parent] -= inner[i];
+                }
             }
+        };
+
+        // undo the construction from `start` on, leaving the values themselves
+        for i in (start..self.inner.len()).rev() {
+            add_to_parent(&mut self.inner, i, false);
+        }
+        for &i in crossing.iter() {
+            add_to_parent(&mut self.inner, i, false);
+        }
+        let tail = self.inner.split_off(start);
+        let mut next = start;
+        for (range, values) in splices {
+            assert!(next <= range.start && range.start <= range.end);
+            self.inner
+                .extend_from_slice(&tail[next - start..range.start - start]);
+            self.inner.extend(values);
+            next = range.end;
+        }
+        self.inner.extend_from_slice(&tail[next - start..]);
+        for &i in crossing.iter() {
+            add_to_parent(&mut self.inner, i, true);
+        }
+        for i in start..self.inner.len() {
+            add_to_parent(&mut self.inner, i, true);
         }
     }
     /// Subtracts a difference from a given index.
@@ -206,7 +286,7 @@ impl FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let lengths = [1, 6, 3, 9, 2];
     /// let mut fenwick_array = FenwickTree::from_iter(lengths);
@@ -234,7 +314,7 @@ impl FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let mut fenwick_array = FenwickTree::from_iter([1, 6, 3, 9].into_iter());
     ///
@@ -272,7 +352,7 @@ impl FenwickTree {
     /// # Examples
     ///
     /// ```
-    /// use ftree::FenwickTree;
+    /// use soam::utils::ftree::FenwickTree;
     ///
     /// let lengths = [1, 6, 3, 9, 2];
     /// let mut fenwick_array = FenwickTree::from_iter(lengths);
@@ -305,7 +385,7 @@ impl FenwickTree {
                 }
             }
 
-            if lsb % 2 > 0 {
+            if !lsb.is_multiple_of(2) {
                 break;
             }
 
@@ -329,6 +409,7 @@ const fn most_significant_bit(n: usize) -> usize {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::useless_conversion)]
 mod tests {
     use super::FenwickTree;
 
@@ -442,7 +523,7 @@ mod tests {
 
     #[test]
     fn test_push_after_initialization() {
-        let mut fenwick = FenwickTree::from_iter([1, 6, 3].into_iter());
+        let mut fenwick = FenwickTree::from_iter([1, 6, 3]);
         fenwick.push(9);
         fenwick.push(2);
 
@@ -452,6 +533,34 @@ mod tests {
             .for_each(|(idx, expected_sum)| assert_eq!(fenwick.prefix_sum(idx, 0), expected_sum));
     }
 
+    #[test]
+    fn test_splice() {
+        let values: Vec<usize> = (0..37).map(|i| (i * 7) % 5).collect();
+        for start in [0, 1, 5, 8, 16, 30, 37] {
+            for (removed, inserted) in [(0, 3), (1, 0), (2, 9)] {
+                let end = (start + removed).min(values.len());
+                let inserted: Vec<usize> = (0..inserted).map(|i| i + 1).collect();
+                let mut fenwick = FenwickTree::from_iter(values.clone());
+                fenwick.splice(start..end, inserted.clone());
+                let mut expected = values.clone();
+                expected.splice(start..end, inserted);
+                assert_eq!(fenwick, FenwickTree::from_iter(expected));
+            }
+        }
+
+        let mut fenwick = FenwickTree::from_iter(values.clone());
+        fenwick.splice_many(vec![
+            (3..4, vec![1, 1]),
+            (10..12, vec![]),
+            (20..20, vec![4]),
+        ]);
+        let mut expected = values;
+        expected.splice(20..20, [4]);
+        expected.splice(10..12, []);
+        expected.splice(3..4, [1, 1]);
+        assert_eq!(fenwick, FenwickTree::from_iter(expected));
+    }
+
     #[test]
     fn test_pop_empty() {
         let mut fenwick: FenwickTree = FenwickTree::new();
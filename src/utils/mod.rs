@@ -1 +1,5 @@
+pub mod cleanup;
 pub mod ftree;
+pub mod rng;
+pub mod statevector;
+pub mod timeline;
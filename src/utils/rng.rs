@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+// SplitMix64, a small seeded generator whose state fits in a checkpoint. Its output only steers
+// the exploration of SOAM, so statistical quality beyond SplitMix64 does not matter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    // A number in `0..n`, or 0 if `n` is 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(42);
+        let first: Vec<usize> = (0..100).map(|_| rng.below(10)).collect();
+        assert!(first.iter().all(|x| *x < 10));
+        assert!(first.iter().any(|x| *x != first[0]));
+        let mut rng = Rng::new(42);
+        let second: Vec<usize> = (0..100).map(|_| rng.below(10)).collect();
+        assert_eq!(first, second);
+        assert_eq!(rng.below(0), 0);
+    }
+}
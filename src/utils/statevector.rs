@@ -0,0 +1,188 @@
+use super::rng::Rng;
+use circuit::{CircuitSeq, Gate};
+use num_complex::Complex64;
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_4};
+
+// A dense state vector, with qubit `q` as bit `q` of the amplitude index. Meant for checking
+// small circuits only: the state takes `2^n` amplitudes.
+#[derive(Debug, Clone)]
+pub struct StateVector {
+    amplitudes: Vec<Complex64>,
+}
+
+impl StateVector {
+    // A random normalized state of `num_qubits` qubits.
+    pub fn random(num_qubits: usize, rng: &mut Rng) -> Self {
+        let mut uniform = || rng.next_u64() as f64 / u64::MAX as f64 - 0.5;
+        let mut amplitudes: Vec<Complex64> = (0..1usize << num_qubits)
+            .map(|_| Complex64::new(uniform(), uniform()))
+            .collect();
+        let norm = amplitudes.iter().map(|a| a.norm_sqr()).sum::<f64>().sqrt();
+        amplitudes.iter_mut().for_each(|a| *a /= norm);
+        StateVector { amplitudes }
+    }
+
+    // The inner product `<self|other>`.
+    pub fn inner(&self, other: &StateVector) -> Complex64 {
+        self.amplitudes
+            .iter()
+            .zip(other.amplitudes.iter())
+            .map(|(a, b)| a.conj() * b)
+            .sum()
+    }
+
+    pub fn apply_circuit(&mut self, circ: &CircuitSeq) {
+        circ.gates.iter().for_each(|gate| self.apply(gate));
+    }
+
+    pub fn apply(&mut self, gate: &Gate) {
+        let i = Complex64::i();
+        let one = Complex64::new(1.0, 0.0);
+        let zero = Complex64::new(0.0, 0.0);
+        let phase = |theta: f64| Complex64::from_polar(1.0, theta);
+        match *gate {
+            Gate::H(q) => {
+                let h = Complex64::new(FRAC_1_SQRT_2, 0.0);
+                self.apply_single(q, [[h, h], [h, -h]])
+            }
+            Gate::X(q) => self.apply_single(q, [[zero, one], [one, zero]]),
+            Gate::Y(q) => self.apply_single(q, [[zero, -i], [i, zero]]),
+            Gate::Z(q) => self.apply_single(q, [[one, zero], [zero, -one]]),
+            Gate::S(q) => self.apply_single(q, [[one, zero], [zero, i]]),
+            Gate::Sdg(q) => self.apply_single(q, [[one, zero], [zero, -i]]),
+            Gate::T(q) => self.apply_single(q, [[one, zero], [zero, phase(FRAC_PI_4)]]),
+            Gate::Tdg(q) => self.apply_single(q, [[one, zero], [zero, phase(-FRAC_PI_4)]]),
+            Gate::RX { param1, q1 } => {
+                let (c, s) = ((param1 / 2.0).cos() * one, (param1 / 2.0).sin() * i);
+                self.apply_single(q1, [[c, -s], [-s, c]])
+            }
+            Gate::RY { param1, q1 } => {
+                let (c, s) = ((param1 / 2.0).cos() * one, (param1 / 2.0).sin() * one);
+                self.apply_single(q1, [[c, -s], [s, c]])
+            }
+            Gate::RZ { param1, q1 } => self.apply_single(
+                q1,
+                [[phase(-param1 / 2.0), zero], [zero, phase(param1 / 2.0)]],
+            ),
+            Gate::SqrtX(q) => {
+                let (a, b) = ((one + i) / 2.0, (one - i) / 2.0);
+                self.apply_single(q, [[a, b], [b, a]])
+            }
+            Gate::SqrtXdg(q) => {
+                let (a, b) = ((one - i) / 2.0, (one + i) / 2.0);
+                self.apply_single(q, [[a, b], [b, a]])
+            }
+            Gate::U {
+                q1,
+                theta,
+                phi,
+                lambda,
+            } => {
+                let (c, s) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+                self.apply_single(
+                    q1,
+                    [
+                        [c * one, -s * phase(lambda)],
+                        [s * phase(phi), c * phase(phi + lambda)],
+                    ],
+                )
+            }
+            Gate::CX { q1, q2 } => self.apply_controlled(&[q1], q2, false),
+            Gate::CZ { q1, q2 } => self.apply_controlled(&[q1], q2, true),
+            Gate::CCX { q1, q2, q3 } => self.apply_controlled(&[q1, q2], q3, false),
+            Gate::CCZ { q1, q2, q3 } => self.apply_controlled(&[q1, q2], q3, true),
+            Gate::Swap { q1, q2 } => {
+                let (b1, b2) = (1 << q1, 1 << q2);
+                for index in 0..self.amplitudes.len() {
+                    if index & b1 != 0 && index & b2 == 0 {
+                        self.amplitudes.swap(index, index ^ b1 ^ b2);
+                    }
+                }
+            }
+            Gate::B => {}
+        }
+    }
+
+    fn apply_single(&mut self, q: usize, matrix: [[Complex64; 2]; 2]) {
+        let bit = 1 << q;
+        for index in 0..self.amplitudes.len() {
+            if index & bit == 0 {
+                let (a0, a1) = (self.amplitudes[index], self.amplitudes[index | bit]);
+                self.amplitudes[index] = matrix[0][0] * a0 + matrix[0][1] * a1;
+                self.amplitudes[index | bit] = matrix[1][0] * a0 + matrix[1][1] * a1;
+            }
+        }
+    }
+
+    // Flips qubit `target` (or its phase, if `phase`) where all the controls are set.
+    fn apply_controlled(&mut self, controls: &[usize], target: usize, phase: bool) {
+        let mask: usize = controls.iter().map(|q| 1 << q).sum();
+        let bit = 1 << target;
+        for index in 0..self.amplitudes.len() {
+            if index & mask == mask && index & bit == 0 {
+                if phase {
+                    self.amplitudes[index | bit] = -self.amplitudes[index | bit];
+                } else {
+                    self.amplitudes.swap(index, index | bit);
+                }
+            }
+        }
+    }
+}
+
+// Whether `a` and `b` are equivalent up to a global phase, checked on `trials` random states:
+// equivalent circuits always pass, and different ones fail with probability close to 1.
+pub fn equivalent(a: &CircuitSeq, b: &CircuitSeq, trials: usize, seed: u64) -> bool {
+    if a.num_qubits != b.num_qubits {
+        return false;
+    }
+    let mut rng = Rng::new(seed);
+    let mut global_phase: Option<Complex64> = None;
+    (0..trials).all(|_| {
+        let mut state_a = StateVector::random(a.num_qubits, &mut rng);
+        let mut state_b = state_a.clone();
+        state_a.apply_circuit(a);
+        state_b.apply_circuit(b);
+        let overlap = state_a.inner(&state_b);
+        let phase = *global_phase.get_or_insert(overlap);
+        (overlap - phase).norm() < 1e-6 && (overlap.norm() - 1.0).abs() < 1e-6
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equivalent() {
+        let circ = |source: &str| {
+            CircuitSeq::new_from_source(&format!("OPENQASM 2.0;\nqreg q[2];\n{}", source))
+        };
+        assert!(equivalent(
+            &circ("t q[0];\nt q[0];\n"),
+            &circ("s q[0];\n"),
+            3,
+            1
+        ));
+        assert!(equivalent(
+            &circ("h q[1];\nx q[1];\nh q[1];\n"),
+            &circ("z q[1];\n"),
+            3,
+            1
+        ));
+        // equal up to a global phase
+        assert!(equivalent(
+            &circ("rz(pi) q[0];\n"),
+            &circ("z q[0];\n"),
+            3,
+            1
+        ));
+        assert!(!equivalent(
+            &circ("cx q[0], q[1];\n"),
+            &circ("cx q[1], q[0];\n"),
+            3,
+            1
+        ));
+        assert!(!equivalent(&circ("t q[0];\n"), &circ("tdg q[0];\n"), 3, 1));
+    }
+}
@@ -0,0 +1,126 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Process ids of the timeline, each shown as a group of tracks: one track per round phase, per
+// worker thread and per Quartz server slot.
+pub const PID_ROUNDS: u32 = 0;
+pub const PID_WORKERS: u32 = 1;
+pub const PID_QUARTZ: u32 = 2;
+
+#[derive(Serialize, Debug, Clone)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    // microseconds since the start of the timeline
+    ts: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: u32,
+    tid: usize,
+    args: Value,
+}
+
+// Spans of a run in the Chrome trace-event format, loadable in perfetto or chrome://tracing.
+#[derive(Debug)]
+pub struct Timeline {
+    start: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline {
+            start: Instant::now(),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Records a span from `start` to now.
+    pub fn span(
+        &self,
+        name: &str,
+        cat: &'static str,
+        pid: u32,
+        tid: usize,
+        start: Instant,
+        args: Value,
+    ) {
+        let ts = start.saturating_duration_since(self.start).as_secs_f64() * 1e6;
+        let dur = start.elapsed().as_secs_f64() * 1e6;
+        self.events.lock().unwrap().push(TraceEvent {
+            name: name.to_string(),
+            cat,
+            ph: "X",
+            ts,
+            dur: Some(dur),
+            pid,
+            tid,
+            args,
+        });
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut events = self.events.lock().unwrap().clone();
+        for (pid, name) in [
+            (PID_ROUNDS, "rounds"),
+            (PID_WORKERS, "workers"),
+            (PID_QUARTZ, "quartz servers"),
+        ] {
+            events.push(TraceEvent {
+                name: "process_name".to_string(),
+                cat: "__metadata",
+                ph: "M",
+                ts: 0.0,
+                dur: None,
+                pid,
+                tid: 0,
+                args: serde_json::json!({ "name": name }),
+            });
+        }
+        serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+    }
+
+    pub fn write(&self, path: &str) {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent).expect("failed to create parent directory");
+        }
+        std::fs::write(path, self.to_json()).expect("failed to write timeline");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_timeline() {
+        let timeline = Timeline::new();
+        let start = Instant::now();
+        timeline.span(
+            "oracle",
+            "oracle",
+            PID_WORKERS,
+            3,
+            start,
+            json!({ "left": 0 }),
+        );
+        let trace: Value = serde_json::from_str(&timeline.to_json()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0]["ph"], "X");
+        assert_eq!(events[0]["tid"], 3);
+        assert_eq!(events[0]["args"]["left"], 0);
+        assert!(events[0]["dur"].as_f64().unwrap() >= 0.0);
+        assert!(events[1..].iter().all(|event| event["ph"] == "M"));
+    }
+}